@@ -0,0 +1,73 @@
+use crate::cli::github_args::GithubArgs;
+use crate::cli::key_provider::KeyArgs;
+use crate::cli::plan::PlanOptions;
+use crate::cli::{crypto_ops, repositories};
+use crate::config::ConfigError;
+use crate::error::Result;
+use crate::plan::{self, Plan, State};
+use clap::Parser;
+use std::path::PathBuf;
+
+/// Apply the changes from a fresh or saved plan
+#[derive(Parser, Debug)]
+pub struct ApplyArgs {
+    /// Path to a raw or encrypted config file (defaults to the one recorded in the plan)
+    #[arg(short, long, required_unless_present = "plan")]
+    pub file: Option<PathBuf>,
+    /// Apply a plan saved by `gsm plan --out` instead of planning again
+    #[arg(long)]
+    pub plan: Option<PathBuf>,
+    #[command(flatten)]
+    pub options: PlanOptions,
+    #[command(flatten)]
+    pub github: GithubArgs,
+    #[command(flatten)]
+    pub encryption: KeyArgs,
+}
+
+pub async fn run(args: &ApplyArgs) -> Result<()> {
+    let saved = args.plan.as_deref().map(Plan::load).transpose()?;
+    let config_path = args
+        .file
+        .clone()
+        .or_else(|| saved.as_ref().map(|plan| plan.config.clone()))
+        .ok_or_else(|| ConfigError::Invalid("no config file given".to_string()))?;
+    let mut credentials = args.encryption.credentials(&config_path)?;
+    let mut config = crypto_ops::load_config(&config_path, &mut credentials)?;
+    let mut state = State::load_or_init(&args.options.state)?;
+    let github_client = args.github.client(&config.org)?;
+    repositories::resolve(&github_client, &mut config).await?;
+
+    let protected = args.options.protected(&config)?;
+    let plan = match saved {
+        Some(plan) => plan,
+        None => {
+            plan::build_plan(
+                &github_client,
+                &config,
+                &config_path,
+                &state,
+                args.options.prune,
+                &protected,
+            )
+            .await?
+        }
+    };
+    plan.print();
+
+    if !plan.has_changes() {
+        println!("Nothing to apply.");
+        return Ok(());
+    }
+    plan::apply_plan(
+        &github_client,
+        &config,
+        &plan,
+        &mut state,
+        &args.options.state,
+        &protected,
+    )
+    .await?;
+    println!("Apply complete!");
+    Ok(())
+}
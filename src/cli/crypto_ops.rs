@@ -1,47 +1,945 @@
-use crate::config::{Config, EncryptedConfig, EncryptedValue};
-use crate::crypto;
-use crate::error::Result;
+use crate::cli::key_provider::{KeyProvider, KeySource};
+use crate::cli::project::{ProjectFile, USER_CONFIG, UserConfig};
+use crate::cli::utils;
+use crate::config::{self, Config, ConfigError, EncryptedConfig, EncryptedValue};
+use crate::crypto::{self, Cipher, CryptoError, Identity, Kdf, Key, TransitKey, Vault};
+use crate::error::{GsmError, Result};
 use base64::{Engine as _, engine::general_purpose};
-use std::collections::HashMap;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use serde_yaml::Value;
+use std::collections::{BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
 
-/// Encrypt a Config into an EncryptedConfig
-pub fn encrypt_config(config: Config, key: &[u8]) -> Result<EncryptedConfig> {
-    let mut encrypted_env = HashMap::new();
-
-    for (k, v) in config.env.iter() {
-        let (salt, nonce, ciphertext) = crypto::encrypt(v.as_bytes(), key)?;
-        encrypted_env.insert(
-            k.clone(),
-            EncryptedValue {
-                salt: general_purpose::STANDARD.encode(&salt),
-                nonce: general_purpose::STANDARD.encode(&nonce),
-                ciphertext: general_purpose::STANDARD.encode(&ciphertext),
-            },
-        );
+/// Top-level key holding the metadata of an encrypted file
+pub const METADATA_KEY: &str = "gsm";
+
+/// Top-level key listing the public keys a file is encrypted to
+pub const RECIPIENTS_KEY: &str = "recipients";
+
+/// Format version written to new files
+///
+/// 1. Each value has its own salt; files have no metadata.
+/// 2. One key per file, derived with the KDF recorded in the metadata.
+/// 3. Values are bound to their key path, the org and a file ID as AES-GCM
+///    associated data, so ciphertexts moved elsewhere fail to decrypt.
+/// 4. A MAC over the whole document detects edits to plaintext fields and
+///    removed entries; the version joins the associated data so the MAC
+///    cannot be dropped by claiming an older version.
+/// 5. The file key may instead be a random key sealed to X25519 recipients.
+/// 6. The file key may instead be a random key wrapped by Vault Transit.
+/// 7. The MAC covers a canonical encoding that tags every key and scalar with
+///    its type, so `1:` and `"1":` no longer collide.
+pub const FORMAT_VERSION: u32 = 7;
+
+/// Metadata stored alongside the secrets of an encrypted file
+#[derive(Debug, Serialize, Deserialize)]
+struct Metadata {
+    version: u32,
+    /// Random identifier bound into every value since format version 3
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    file_id: Option<String>,
+    /// How the file key is derived from the password, for password files
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    kdf: Option<KdfParams>,
+    /// The file key sealed to each recipient, for files shared with recipients
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    recipients: Vec<SealedKey>,
+    /// The file key wrapped by a Vault Transit key, for envelope encryption
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    transit: Option<WrappedKey>,
+    cipher: Cipher,
+    /// MAC over the rest of the document since format version 4
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    mac: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct KdfParams {
+    #[serde(flatten)]
+    kdf: Kdf,
+    /// Salt from which the file key is derived
+    salt: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct SealedKey {
+    /// Base64 X25519 public key of the recipient
+    public_key: String,
+    sealed_key: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct WrappedKey {
+    #[serde(flatten)]
+    transit: TransitKey,
+    /// Ciphertext returned by Vault, such as `vault:v1:...`
+    wrapped_key: String,
+}
+
+impl Metadata {
+    /// Read the metadata of a document, if it has any
+    fn read(document: &Value) -> Result<Option<Metadata>> {
+        let Some(metadata) = document.get(METADATA_KEY) else {
+            return Ok(None);
+        };
+        if let Some(metadata) = Self::salt_only(metadata) {
+            return Ok(Some(metadata));
+        }
+        let metadata: Metadata = serde_yaml::from_value(metadata.clone()).map_err(|e| {
+            ConfigError::Invalid(format!("invalid '{}' metadata: {}", METADATA_KEY, e))
+        })?;
+        if metadata.version > FORMAT_VERSION {
+            return Err(ConfigError::Invalid(format!(
+                "the file uses format version {}, but this gsm only reads up to {}; upgrade gsm",
+                metadata.version, FORMAT_VERSION
+            ))
+            .into());
+        }
+        if metadata.version < 2 {
+            return Err(ConfigError::Invalid(format!(
+                "format version {} files have no '{}' metadata",
+                metadata.version, METADATA_KEY
+            ))
+            .into());
+        }
+        let protections = [
+            metadata.kdf.is_some(),
+            !metadata.recipients.is_empty(),
+            metadata.transit.is_some(),
+        ];
+        if protections.iter().filter(|present| **present).count() != 1 {
+            return Err(ConfigError::Invalid(format!(
+                "invalid '{}' metadata: needs exactly one of `kdf`, `recipients` or `transit`",
+                METADATA_KEY
+            ))
+            .into());
+        }
+        if let Some(params) = &metadata.kdf {
+            params.kdf.check_cost()?;
+        }
+        if metadata.version >= 3 && metadata.file_id.is_none() {
+            return Err(ConfigError::Invalid(format!(
+                "invalid '{}' metadata: missing field `file_id`",
+                METADATA_KEY
+            ))
+            .into());
+        }
+        Ok(Some(metadata))
     }
 
-    Ok(EncryptedConfig {
-        org: config.org,
-        repositories: config.repositories,
-        env: encrypted_env,
+    /// Metadata written before format versions were recorded, holding only
+    /// the salt of a file key derived with PBKDF2; such files are version 2
+    fn salt_only(metadata: &Value) -> Option<Metadata> {
+        let mapping = metadata.as_mapping()?;
+        let salt = mapping.get("salt")?.as_str()?;
+        if mapping.len() != 1 {
+            return None;
+        }
+        Some(Metadata {
+            version: 2,
+            file_id: None,
+            kdf: Some(KdfParams {
+                kdf: Kdf::Pbkdf2Sha256 {
+                    iterations: crypto::PBKDF2_ITER,
+                },
+                salt: salt.to_string(),
+            }),
+            recipients: Vec::new(),
+            transit: None,
+            cipher: Cipher::default(),
+            mac: None,
+        })
+    }
+}
+
+/// Format version of an encrypted document
+pub fn format_version(document: &Value) -> Result<u32> {
+    Ok(Metadata::read(document)?.map_or(1, |metadata| metadata.version))
+}
+
+/// How the key of a newly encrypted file is protected
+#[derive(Debug, Clone, PartialEq)]
+pub enum Protection {
+    /// Derived from the password with this KDF
+    Password(Kdf),
+    /// A random key sealed to each of these base64 X25519 public keys
+    Recipients(Vec<String>),
+    /// A random key wrapped by this Vault Transit key
+    Transit(TransitKey),
+}
+
+impl Protection {
+    /// What protects the file key, for messages
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Protection::Password(_) => "a password",
+            Protection::Recipients(_) => "recipients",
+            Protection::Transit(_) => "Vault Transit",
+        }
+    }
+}
+
+/// What protects the key of an encrypted document now, as named by
+/// [`Protection::kind`]; files written before file keys use a password
+pub fn protection_kind(document: &Value) -> Result<&'static str> {
+    Ok(match Metadata::read(document)? {
+        Some(Metadata {
+            transit: Some(_), ..
+        }) => "Vault Transit",
+        Some(metadata) if !metadata.recipients.is_empty() => "recipients",
+        _ => "a password",
     })
 }
 
+/// How the file at `path` should be protected: sealed to the recipients it
+/// lists, or else those of the nearest `.gsm` file, then wrapped by the
+/// `.gsm` file's Transit key, and with the password and `kdf` otherwise
+pub fn protection_for(path: &Path, document: &Value, kdf: &Kdf) -> Result<Protection> {
+    let project = ProjectFile::find(path)?.unwrap_or_default();
+    let recipients = match document.get(RECIPIENTS_KEY) {
+        Some(recipients) => serde_yaml::from_value(recipients.clone()).map_err(|_| {
+            ConfigError::Invalid(format!(
+                "'{}' in '{}' must be a list of public keys",
+                RECIPIENTS_KEY,
+                path.display()
+            ))
+        })?,
+        None => project.recipients,
+    };
+    Ok(if !recipients.is_empty() {
+        Protection::Recipients(recipients)
+    } else if let Some(transit) = project.transit {
+        Protection::Transit(transit)
+    } else {
+        Protection::Password(kdf.clone())
+    })
+}
+
+/// Whether an encrypted document should be rewritten in the newest format
+/// with `protection`, including files that mix in values written before file
+/// keys and files whose recipients changed
+pub fn needs_migration(document: &mut Value, protection: &Protection) -> Result<bool> {
+    let current = match Metadata::read(document)? {
+        Some(metadata) => {
+            metadata.version == FORMAT_VERSION
+                && match protection {
+                    Protection::Password(kdf) => {
+                        metadata.kdf.is_some_and(|params| params.kdf == *kdf)
+                    }
+                    Protection::Recipients(recipients) => {
+                        let sealed_to: BTreeSet<&str> = metadata
+                            .recipients
+                            .iter()
+                            .map(|sealed| sealed.public_key.as_str())
+                            .collect();
+                        sealed_to == recipients.iter().map(String::as_str).collect()
+                    }
+                    Protection::Transit(transit) => metadata
+                        .transit
+                        .is_some_and(|wrapped| wrapped.transit == *transit),
+                }
+        }
+        None => false,
+    };
+    Ok(!current
+        || config::secret_values_mut(document)
+            .iter()
+            .any(|(_, secret)| secret.get("salt").is_some()))
+}
+
+/// Encrypts and decrypts the secrets of one file
+///
+/// A single key is used per file, derived from the password with the KDF and
+/// salt recorded in its `gsm` metadata, sealed to each of its recipients or
+/// wrapped by Vault Transit, so each value only stores its nonce. Since
+/// format version 3 each value is also bound to its key path, the file's
+/// `org` and the file ID, and since version 4 the whole document is covered
+/// by a MAC. Values written before file keys carry their own salt and are
+/// still decrypted with a key derived for that value.
+pub struct FileCipher {
+    /// Password for values that carry their own salt
+    password: Option<Vec<u8>>,
+    version: u32,
+    key: Option<Key>,
+    /// Org and file ID bound into values, for files that use associated data
+    binding: Option<(String, String)>,
+}
+
+impl FileCipher {
+    /// Unlock the key of an encrypted document and verify the document's MAC
+    pub fn open(document: &Value, credentials: &mut Credentials) -> Result<Self> {
+        let cipher = Self::from_metadata(document, credentials)?;
+        cipher.verify(document)?;
+        Ok(cipher)
+    }
+
+    /// Like [`FileCipher::open`], but gives a document without metadata a
+    /// new key protected as `protection`, in the current format
+    pub fn init(
+        document: &mut Value,
+        credentials: &mut Credentials,
+        protection: &Protection,
+    ) -> Result<Self> {
+        if document.get(METADATA_KEY).is_some() {
+            return Self::open(document, credentials);
+        }
+        let mut file_id = [0u8; 16];
+        rand::rng().fill_bytes(&mut file_id);
+        let file_id = general_purpose::STANDARD.encode(file_id);
+        let mut metadata = Metadata {
+            version: FORMAT_VERSION,
+            file_id: Some(file_id.clone()),
+            kdf: None,
+            recipients: Vec::new(),
+            transit: None,
+            cipher: Cipher::default(),
+            mac: None,
+        };
+        let (key, password) = match protection {
+            Protection::Password(kdf) => {
+                let password = credentials.password()?.to_vec();
+                let salt = crypto::generate_salt();
+                let key = kdf.derive(&password, &salt)?;
+                metadata.kdf = Some(KdfParams {
+                    kdf: kdf.clone(),
+                    salt: general_purpose::STANDARD.encode(salt),
+                });
+                (key, Some(password))
+            }
+            Protection::Recipients(recipients) => {
+                let key = crypto::generate_key();
+                for public_key in recipients {
+                    let sealed =
+                        crypto::wrap_key(&key, &general_purpose::STANDARD.decode(public_key)?)?;
+                    metadata.recipients.push(SealedKey {
+                        public_key: public_key.clone(),
+                        sealed_key: general_purpose::STANDARD.encode(sealed),
+                    });
+                }
+                (key, None)
+            }
+            Protection::Transit(transit) => {
+                let key = crypto::generate_key();
+                let wrapped_key = transit.wrap_key(&key, credentials.vault()?)?;
+                metadata.transit = Some(WrappedKey {
+                    transit: transit.clone(),
+                    wrapped_key,
+                });
+                (key, None)
+            }
+        };
+
+        let Some(mapping) = document.as_mapping_mut() else {
+            return Err(ConfigError::Invalid("config is not a YAML mapping".to_string()).into());
+        };
+        mapping.insert(
+            Value::String(METADATA_KEY.to_string()),
+            serde_yaml::to_value(metadata)?,
+        );
+        Ok(FileCipher {
+            password,
+            version: FORMAT_VERSION,
+            key: Some(key),
+            binding: Some((document_org(document).to_string(), file_id)),
+        })
+    }
+
+    fn from_metadata(document: &Value, credentials: &mut Credentials) -> Result<Self> {
+        let Some(metadata) = Metadata::read(document)? else {
+            // Files without metadata only hold values with their own salt
+            return Ok(FileCipher {
+                password: Some(credentials.password()?.to_vec()),
+                version: 1,
+                key: None,
+                binding: None,
+            });
+        };
+        // Every cipher so far is AES-256-GCM; new ones get dispatched here
+        let Cipher::Aes256Gcm = metadata.cipher;
+        let (key, password) = match (&metadata.kdf, &metadata.transit) {
+            (Some(params), _) => {
+                let password = credentials.password()?.to_vec();
+                let salt = general_purpose::STANDARD.decode(&params.salt)?;
+                (params.kdf.derive(&password, &salt)?, Some(password))
+            }
+            (None, Some(wrapped)) => {
+                let vault = credentials.vault()?;
+                (
+                    wrapped.transit.unwrap_key(&wrapped.wrapped_key, vault)?,
+                    None,
+                )
+            }
+            (None, None) => {
+                let identity = credentials.identity()?;
+                let public_key = general_purpose::STANDARD.encode(identity.public_key());
+                let sealed = metadata
+                    .recipients
+                    .iter()
+                    .find(|sealed| sealed.public_key == public_key)
+                    .ok_or_else(|| {
+                        CryptoError::DecryptionFailed(format!(
+                            "the identity {} is not a recipient of this file",
+                            public_key
+                        ))
+                    })?;
+                let sealed = general_purpose::STANDARD.decode(&sealed.sealed_key)?;
+                (identity.unwrap_key(&sealed)?, None)
+            }
+        };
+        Ok(FileCipher {
+            password,
+            version: metadata.version,
+            key: Some(key),
+            binding: metadata
+                .file_id
+                .map(|file_id| (document_org(document).to_string(), file_id)),
+        })
+    }
+
+    /// Check the MAC of a document, for files that have one
+    fn verify(&self, document: &Value) -> Result<()> {
+        let Some(key) = self.mac_key() else {
+            return Ok(());
+        };
+        let tag = document
+            .get(METADATA_KEY)
+            .and_then(|metadata| metadata.get("mac"))
+            .and_then(Value::as_str)
+            .ok_or_else(|| {
+                CryptoError::IntegrityCheckFailed(format!(
+                    "the '{}' metadata has no MAC",
+                    METADATA_KEY
+                ))
+            })?;
+        let tag = general_purpose::STANDARD.decode(tag)?;
+        crypto::verify_mac(&key, &canonical_bytes(document, self.version)?, &tag)?;
+        Ok(())
+    }
+
+    /// Store the MAC of a document once all its changes are made
+    pub fn seal(&self, document: &mut Value) -> Result<()> {
+        let Some(key) = self.mac_key() else {
+            return Ok(());
+        };
+        let tag = crypto::mac(&key, &canonical_bytes(document, self.version)?);
+        if let Some(metadata) = document
+            .get_mut(METADATA_KEY)
+            .and_then(Value::as_mapping_mut)
+        {
+            metadata.insert(
+                Value::String("mac".to_string()),
+                Value::String(general_purpose::STANDARD.encode(tag)),
+            );
+        }
+        Ok(())
+    }
+
+    fn mac_key(&self) -> Option<Key> {
+        match &self.key {
+            Some(key) if self.version >= 4 => Some(crypto::subkey(key, "gsm file mac")),
+            _ => None,
+        }
+    }
+
+    /// Associated data binding the value at key path `name` to this file
+    fn associated_data(&self, name: &str) -> Vec<u8> {
+        match &self.binding {
+            Some((org, file_id)) if self.version >= 4 => {
+                format!("{}\0{}\0{}\0{}", self.version, org, file_id, name).into_bytes()
+            }
+            Some((org, file_id)) => format!("{}\0{}\0{}", org, file_id, name).into_bytes(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Encrypt the secret at key path `name` into its YAML form
+    pub fn encrypt(&self, name: &str, plaintext: &str) -> Result<Value> {
+        let key = self.key.as_ref().ok_or_else(|| {
+            CryptoError::KeyError("the file has no metadata to take a key from".to_string())
+        })?;
+        let (nonce, ciphertext) =
+            crypto::encrypt_with_key(plaintext.as_bytes(), key, &self.associated_data(name))?;
+        Ok(serde_yaml::to_value(EncryptedValue {
+            salt: None,
+            nonce: general_purpose::STANDARD.encode(&nonce),
+            ciphertext: general_purpose::STANDARD.encode(&ciphertext),
+        })?)
+    }
+
+    /// Decrypt the secret at key path `name` from its YAML form
+    pub fn decrypt(&self, name: &str, secret: &Value) -> Result<String> {
+        let value: EncryptedValue = serde_yaml::from_value(secret.clone())?;
+        let nonce = general_purpose::STANDARD.decode(&value.nonce)?;
+        let ciphertext = general_purpose::STANDARD.decode(&value.ciphertext)?;
+        let plaintext = match (&value.salt, &self.key) {
+            (Some(salt), _) => {
+                let salt = general_purpose::STANDARD.decode(salt)?;
+                let password = self.password.as_deref().ok_or_else(|| {
+                    CryptoError::DecryptionFailed(format!(
+                        "'{}' needs a password, but the file is shared with recipients",
+                        name
+                    ))
+                })?;
+                crypto::decrypt(&ciphertext, password, &salt, &nonce)
+            }
+            (None, Some(key)) => {
+                crypto::decrypt_with_key(&ciphertext, key, &nonce, &self.associated_data(name))
+            }
+            (None, None) => {
+                return Err(CryptoError::DecryptionFailed(format!(
+                    "the value has no salt and the file no '{}' metadata",
+                    METADATA_KEY
+                ))
+                .into());
+            }
+        }
+        .map_err(|_| {
+            let moved = if self.binding.is_some() {
+                ", or the value was moved from another key, file or org"
+            } else {
+                ""
+            };
+            CryptoError::DecryptionFailed(format!(
+                "'{}' could not be decrypted: wrong key{}",
+                name, moved
+            ))
+        })?;
+        Ok(String::from_utf8(plaintext)?)
+    }
+}
+
+/// The bytes a format version `version` MAC covers: the document without
+/// its MAC, in a form that formatting and key order do not affect
+///
+/// Since version 7 every node is written with a type tag and a length, and
+/// mapping entries are sorted by their encoded key. Older versions used JSON
+/// with keys sorted as strings, which is reproduced here for their MACs.
+pub fn canonical_bytes(document: &Value, version: u32) -> Result<Vec<u8>> {
+    let mut document = document.clone();
+    if let Some(metadata) = document
+        .get_mut(METADATA_KEY)
+        .and_then(Value::as_mapping_mut)
+    {
+        metadata.remove("mac");
+    }
+    if version < 7 {
+        let json = sorted_json(serde_json::to_value(&document)?);
+        return Ok(serde_json::to_vec(&json)?);
+    }
+    let mut bytes = Vec::new();
+    encode_canonical(&document, &mut bytes);
+    Ok(bytes)
+}
+
+/// Rebuild JSON objects with their keys inserted in sorted order, which
+/// holds whether or not serde_json preserves insertion order
+fn sorted_json(value: serde_json::Value) -> serde_json::Value {
+    match value {
+        serde_json::Value::Object(object) => {
+            let mut entries: Vec<_> = object.into_iter().collect();
+            entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+            serde_json::Value::Object(
+                entries
+                    .into_iter()
+                    .map(|(key, value)| (key, sorted_json(value)))
+                    .collect(),
+            )
+        }
+        serde_json::Value::Array(items) => {
+            serde_json::Value::Array(items.into_iter().map(sorted_json).collect())
+        }
+        scalar => scalar,
+    }
+}
+
+/// Append the canonical encoding of a YAML node: a one-letter type tag,
+/// then a length or count and `:`, then the content
+fn encode_canonical(value: &Value, out: &mut Vec<u8>) {
+    fn text(tag: u8, text: &str, out: &mut Vec<u8>) {
+        out.push(tag);
+        out.extend_from_slice(format!("{}:", text.len()).as_bytes());
+        out.extend_from_slice(text.as_bytes());
+    }
+    match value {
+        Value::Null => out.push(b'n'),
+        Value::Bool(true) => out.push(b't'),
+        Value::Bool(false) => out.push(b'f'),
+        Value::Number(number) => text(b'd', &number.to_string(), out),
+        Value::String(string) => text(b's', string, out),
+        Value::Sequence(items) => {
+            out.extend_from_slice(format!("l{}:", items.len()).as_bytes());
+            for item in items {
+                encode_canonical(item, out);
+            }
+        }
+        Value::Mapping(mapping) => {
+            let mut entries: Vec<(Vec<u8>, &Value)> = mapping
+                .iter()
+                .map(|(key, value)| {
+                    let mut encoded = Vec::new();
+                    encode_canonical(key, &mut encoded);
+                    (encoded, value)
+                })
+                .collect();
+            entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+            out.extend_from_slice(format!("m{}:", entries.len()).as_bytes());
+            for (key, value) in entries {
+                out.extend_from_slice(&key);
+                encode_canonical(value, out);
+            }
+        }
+        Value::Tagged(tagged) => {
+            text(b'g', &tagged.tag.to_string(), out);
+            encode_canonical(&tagged.value, out);
+        }
+    }
+}
+
+/// The org a document's values are bound to; empty for files that inherit it
+fn document_org(document: &Value) -> &str {
+    document
+        .get("org")
+        .and_then(Value::as_str)
+        .unwrap_or_default()
+}
+
+/// Encrypt every plaintext secret of a config document in place
+///
+/// Values that are already encrypted are left untouched; `protection` only
+/// applies to documents that have no metadata yet.
+pub fn encrypt_document(
+    document: &mut Value,
+    credentials: &mut Credentials,
+    protection: &Protection,
+) -> Result<()> {
+    let cipher = FileCipher::init(document, credentials, protection)?;
+    for (name, secret) in config::secret_values_mut(document) {
+        if let Value::String(plaintext) = secret {
+            *secret = cipher.encrypt(&name, plaintext)?;
+        }
+    }
+    cipher.seal(document)
+}
+
+/// Decrypt every encrypted secret of a config document in place, dropping
+/// its metadata
+pub fn decrypt_document(document: &mut Value, credentials: &mut Credentials) -> Result<()> {
+    let cipher = FileCipher::open(document, credentials)?;
+    for (name, secret) in config::secret_values_mut(document) {
+        if secret.is_mapping() {
+            *secret = Value::String(cipher.decrypt(&name, secret)?);
+        }
+    }
+    take_metadata(document);
+    Ok(())
+}
+
+/// Encrypt a Config into an EncryptedConfig, deriving its key with the default KDF
+///
+/// The MAC is taken over the serialized EncryptedConfig rather than the
+/// document it was built from, so the typed value always verifies.
+pub fn encrypt_config(config: Config, key: &[u8]) -> Result<EncryptedConfig> {
+    let mut document = serde_yaml::to_value(config)?;
+    let mut credentials = Credentials::with_password(key);
+    let protection = Protection::Password(Kdf::default());
+    let cipher = FileCipher::init(&mut document, &mut credentials, &protection)?;
+    for (name, secret) in config::secret_values_mut(&mut document) {
+        if let Value::String(plaintext) = secret {
+            *secret = cipher.encrypt(&name, plaintext)?;
+        }
+    }
+    let encrypted_config: EncryptedConfig = serde_yaml::from_value(document)?;
+    let mut document = serde_yaml::to_value(encrypted_config)?;
+    cipher.seal(&mut document)?;
+    Ok(serde_yaml::from_value(document)?)
+}
+
 /// Decrypt an EncryptedConfig into a Config
 pub fn decrypt_config(encrypted_config: EncryptedConfig, key: &[u8]) -> Result<Config> {
-    let mut raw_env = HashMap::new();
+    let mut document = serde_yaml::to_value(encrypted_config)?;
+    decrypt_document(&mut document, &mut Credentials::with_password(key))?;
+    Ok(serde_yaml::from_value(document)?)
+}
 
-    for (k, v) in encrypted_config.env.iter() {
-        let salt = general_purpose::STANDARD.decode(&v.salt)?;
-        let nonce = general_purpose::STANDARD.decode(&v.nonce)?;
-        let ciphertext = general_purpose::STANDARD.decode(&v.ciphertext)?;
-        let plaintext = crypto::decrypt(&ciphertext, key, &salt, &nonce)?;
-        raw_env.insert(k.clone(), String::from_utf8(plaintext)?);
-    }
+/// Remove the metadata of an encrypted document
+fn take_metadata(document: &mut Value) -> Option<Value> {
+    document.as_mapping_mut()?.remove(METADATA_KEY)
+}
+
+/// What a document held before [`decrypt_document_tracked`] decrypted it
+pub struct PreviousSecrets {
+    metadata: Option<Value>,
+    org: String,
+    /// Plaintext and ciphertext of each secret by key path
+    values: HashMap<String, (String, Value)>,
+}
 
-    Ok(Config {
-        org: encrypted_config.org,
-        repositories: encrypted_config.repositories,
-        env: raw_env,
+/// Decrypt a config document in place, remembering each secret's ciphertext
+/// so unchanged values can be re-encrypted identically
+pub fn decrypt_document_tracked(
+    document: &mut Value,
+    credentials: &mut Credentials,
+) -> Result<PreviousSecrets> {
+    let cipher = FileCipher::open(document, credentials)?;
+    let mut values = HashMap::new();
+    for (name, secret) in config::secret_values_mut(document) {
+        if secret.is_mapping() {
+            let plaintext = cipher.decrypt(&name, secret)?;
+            let ciphertext = std::mem::replace(secret, Value::String(plaintext.clone()));
+            values.insert(name, (plaintext, ciphertext));
+        }
+    }
+    Ok(PreviousSecrets {
+        metadata: take_metadata(document),
+        org: document_org(document).to_string(),
+        values,
     })
 }
+
+/// Encrypt the plaintext secrets of a config document, reusing the previous
+/// metadata and the ciphertext of every value that did not change
+///
+/// Changing `org` re-encrypts every value, since values are bound to it.
+/// `protection` only applies when the previous document had no metadata.
+pub fn reencrypt_document(
+    document: &mut Value,
+    previous: &PreviousSecrets,
+    credentials: &mut Credentials,
+    protection: &Protection,
+) -> Result<()> {
+    // The previous document was verified when it was decrypted
+    let cipher = match (&previous.metadata, document.as_mapping_mut()) {
+        (Some(metadata), Some(mapping)) => {
+            mapping.insert(Value::String(METADATA_KEY.to_string()), metadata.clone());
+            FileCipher::from_metadata(document, credentials)?
+        }
+        _ => FileCipher::init(document, credentials, protection)?,
+    };
+    let same_org = document_org(document) == previous.org;
+    for (name, secret) in config::secret_values_mut(document) {
+        let Value::String(plaintext) = secret else {
+            continue;
+        };
+        *secret = match previous.values.get(&name) {
+            Some((old, ciphertext)) if same_org && old == plaintext => ciphertext.clone(),
+            _ => cipher.encrypt(&name, plaintext)?,
+        };
+    }
+    cipher.seal(document)
+}
+
+/// Re-encrypt a document from the `old` password to the `new` one, keeping
+/// its KDF, and check that the result decrypts to the same secrets
+///
+/// Files shared with recipients or wrapped by Vault Transit have no password
+/// and are left untouched, in which case this returns `false`.
+pub fn rekey_document(
+    document: &mut Value,
+    old: &mut Credentials,
+    new: &mut Credentials,
+) -> Result<bool> {
+    let kdf = match Metadata::read(document)? {
+        Some(Metadata {
+            kdf: Some(params), ..
+        }) => params.kdf,
+        Some(_) => return Ok(false),
+        None => Kdf::default(),
+    };
+    decrypt_document(document, old)?;
+    let plaintext = document.clone();
+    encrypt_document(document, new, &Protection::Password(kdf))?;
+
+    let mut check = document.clone();
+    decrypt_document(&mut check, new)?;
+    if check != plaintext {
+        return Err(CryptoError::DecryptionFailed(
+            "the re-encrypted file does not decrypt to the same secrets".to_string(),
+        )
+        .into());
+    }
+    Ok(true)
+}
+
+/// Whether a config document is encrypted
+pub fn is_encrypted(document: &mut Value) -> bool {
+    document.get(METADATA_KEY).is_some()
+        || config::secret_values_mut(document)
+            .iter()
+            .any(|(_, secret)| secret.is_mapping())
+}
+
+/// What unlocks encrypted files: a password from a [`KeyProvider`] or the
+/// X25519 identity at [`identity_path`], each read only once a file needs it
+#[derive(Default)]
+pub struct Credentials {
+    /// Password for files protected with a KDF
+    pub password: Option<Vec<u8>>,
+    /// Where to read the password from, `ENCRYPTION_KEY` by default
+    pub provider: Option<Box<dyn KeyProvider>>,
+    /// Identity for files shared with recipients
+    pub identity: Option<Identity>,
+    /// Vault to unwrap and wrap file keys with, for files wrapped by Vault
+    /// Transit; built from the settings below and `VAULT_TOKEN` by default
+    pub vault: Option<Vault>,
+    /// Vault address from the command line, ahead of `VAULT_ADDR` and the
+    /// user config
+    pub vault_address: Option<String>,
+    /// Allow a plain `http://` Vault address
+    pub vault_allow_http: bool,
+}
+
+impl Credentials {
+    /// Credentials holding only a password
+    pub fn with_password(password: &[u8]) -> Self {
+        Credentials {
+            password: Some(password.to_vec()),
+            ..Default::default()
+        }
+    }
+
+    pub fn password(&mut self) -> Result<&[u8]> {
+        let password = match self.password.take() {
+            Some(password) => password,
+            None => match &self.provider {
+                Some(provider) => provider.key()?,
+                None => KeySource::default().provider()?.key()?,
+            },
+        };
+        Ok(self.password.insert(password))
+    }
+
+    pub fn vault(&mut self) -> Result<&Vault> {
+        let vault = match self.vault.take() {
+            Some(vault) => vault,
+            None => {
+                let settings = UserConfig::load()?.vault;
+                let address = self
+                    .vault_address
+                    .clone()
+                    .or_else(|| std::env::var("VAULT_ADDR").ok())
+                    .or(settings.address)
+                    .ok_or_else(|| {
+                        CryptoError::Transit(format!(
+                            "no Vault address; set VAULT_ADDR, --vault-addr \
+                             or vault.address in '{}'",
+                            USER_CONFIG
+                        ))
+                    })?;
+                let token = std::env::var("VAULT_TOKEN")
+                    .map_err(|_| CryptoError::Transit("VAULT_TOKEN is not set".to_string()))?;
+                Vault::new(
+                    &address,
+                    &token,
+                    self.vault_allow_http || settings.allow_http,
+                )?
+            }
+        };
+        Ok(self.vault.insert(vault))
+    }
+
+    pub fn identity(&mut self) -> Result<&Identity> {
+        let identity = match self.identity.take() {
+            Some(identity) => identity,
+            None => read_identity(&identity_path()?)?,
+        };
+        Ok(self.identity.insert(identity))
+    }
+}
+
+/// Identity file from `GSM_IDENTITY`, defaulting to `~/.gsm/identity`
+pub fn identity_path() -> Result<PathBuf> {
+    match std::env::var_os("GSM_IDENTITY") {
+        Some(path) => Ok(PathBuf::from(path)),
+        None => Ok(utils::home_dir()?.join(".gsm").join("identity")),
+    }
+}
+
+/// Read an identity file: `#` comments and the base64 secret key
+pub fn read_identity(path: &Path) -> Result<Identity> {
+    let content = utils::read_private(path).map_err(|e| match e {
+        GsmError::Io(e) => ConfigError::Invalid(format!(
+            "cannot read the identity '{}' ({}); create one with `gsm keygen` or set GSM_IDENTITY",
+            path.display(),
+            e
+        ))
+        .into(),
+        e => e,
+    })?;
+    let secret_key = content
+        .lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty() && !line.starts_with('#'))
+        .ok_or_else(|| ConfigError::Invalid(format!("'{}' holds no secret key", path.display())))?;
+    Ok(Identity::from_secret_key(
+        &general_purpose::STANDARD.decode(secret_key)?,
+    )?)
+}
+
+/// Decrypts the encrypted layers of a config chain, reading credentials
+/// only once a layer actually needs them
+///
+/// Each file's MAC only covers that file, so the files an encrypted file
+/// extends or includes must be encrypted too: otherwise whoever can edit a
+/// plaintext base could redirect the secrets to another org or repository.
+pub struct LayerDecrypter<'a> {
+    credentials: &'a mut Credentials,
+    /// Files referenced by an encrypted layer, with the layer referencing them
+    referenced: HashMap<PathBuf, PathBuf>,
+}
+
+impl<'a> LayerDecrypter<'a> {
+    pub fn new(credentials: &'a mut Credentials) -> Self {
+        LayerDecrypter {
+            credentials,
+            referenced: HashMap::new(),
+        }
+    }
+
+    pub fn decrypt(&mut self, path: &Path, layer: &mut Value) -> Result<()> {
+        if !is_encrypted(layer) {
+            if let Some(from) = self.referenced.get(&canonical(path)) {
+                return Err(CryptoError::IntegrityCheckFailed(format!(
+                    "'{}' is not encrypted, but the encrypted '{}' extends or includes it",
+                    path.display(),
+                    from.display()
+                ))
+                .into());
+            }
+            return Ok(());
+        }
+        decrypt_document(layer, self.credentials).map_err(|e| in_file(path, e))?;
+        for reference in config::document_references(path, layer)? {
+            self.referenced
+                .insert(canonical(&reference), path.to_path_buf());
+        }
+        Ok(())
+    }
+}
+
+/// The canonical form of a path, or the path itself when it does not exist
+fn canonical(path: &Path) -> PathBuf {
+    std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// Name the file in integrity errors, since it may be any of several files
+pub fn in_file(path: &Path, error: GsmError) -> GsmError {
+    match error {
+        GsmError::Crypto(CryptoError::IntegrityCheckFailed(reason)) => {
+            CryptoError::IntegrityCheckFailed(format!("'{}': {}", path.display(), reason)).into()
+        }
+        e => e,
+    }
+}
+
+/// Load a raw or encrypted config, resolving `extends` and `include`
+///
+/// Each encrypted file in the chain is decrypted on its own before the
+/// layers are merged.
+pub fn load_config(path: &Path, credentials: &mut Credentials) -> Result<Config> {
+    let mut decrypter = LayerDecrypter::new(credentials);
+    let document = config::load_document(path, &mut |layer_path, layer| {
+        decrypter.decrypt(layer_path, layer)
+    })?;
+    Ok(config::config_from_document(document)?)
+}
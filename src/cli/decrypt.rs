@@ -1,9 +1,10 @@
-use crate::cli::{crypto_ops, utils};
-use crate::config::EncryptedConfig;
+use crate::cli::crypto_ops::{self, Credentials};
+use crate::cli::key_provider::KeyArgs;
+use crate::cli::utils;
 use crate::error::Result;
 use clap::Parser;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 /// Decrypt an encrypted config file
 #[derive(Parser, Debug)]
@@ -14,6 +15,8 @@ pub struct DecryptArgs {
     /// Output file path (optional)
     #[arg(short, long)]
     pub output: Option<PathBuf>,
+    #[command(flatten)]
+    pub encryption: KeyArgs,
 }
 
 pub fn run(args: &DecryptArgs) -> Result<()> {
@@ -22,14 +25,22 @@ pub fn run(args: &DecryptArgs) -> Result<()> {
         .output
         .clone()
         .unwrap_or_else(|| utils::get_output_path(input_path, "decrypted", "yaml"));
+    let mut credentials = args.encryption.credentials(input_path)?;
+    decrypt_file(input_path, &output_path, &mut credentials)
+}
 
+/// Decrypt the encrypted config file at `input_path` into `output_path`
+pub fn decrypt_file(
+    input_path: &Path,
+    output_path: &Path,
+    credentials: &mut Credentials,
+) -> Result<()> {
     let content = fs::read_to_string(input_path)?;
-    let encrypted_config: EncryptedConfig = serde_yaml::from_str(&content)?;
-    let key = std::env::var("ENCRYPTION_KEY")?;
-    let config = crypto_ops::decrypt_config(encrypted_config, key.as_bytes())?;
+    let mut document: serde_yaml::Value = serde_yaml::from_str(&content)?;
+    crypto_ops::decrypt_document(&mut document, credentials)?;
 
-    let yaml = serde_yaml::to_string(&config)?;
-    fs::write(&output_path, yaml)?;
+    let yaml = serde_yaml::to_string(&document)?;
+    fs::write(output_path, yaml)?;
     println!(
         "Decrypted '{}' to '{}' ✅",
         input_path.display(),
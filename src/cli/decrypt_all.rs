@@ -1,3 +1,4 @@
+use crate::cli::key_provider::KeyArgs;
 use crate::cli::{decrypt, utils};
 use crate::error::Result;
 use clap::Parser;
@@ -9,19 +10,17 @@ pub struct DecryptAllArgs {
     /// Parent input folder containing 'encrypted' and 'raw' subfolders
     #[arg(short, long)]
     pub input: PathBuf,
+    #[command(flatten)]
+    pub encryption: KeyArgs,
 }
 
 pub fn run(args: &DecryptAllArgs) -> Result<()> {
+    // One set of credentials, so a prompted key is only asked for once
+    let mut credentials = args.encryption.credentials(&args.input)?;
     utils::process_directory(
         &args.input,
         "encrypted",
         "raw",
-        |input_path, output_path| {
-            let decrypt_args = decrypt::DecryptArgs {
-                file: input_path.to_path_buf(),
-                output: Some(output_path.to_path_buf()),
-            };
-            decrypt::run(&decrypt_args)
-        },
+        |input_path, output_path| decrypt::decrypt_file(input_path, output_path, &mut credentials),
     )
 }
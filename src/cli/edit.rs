@@ -0,0 +1,124 @@
+use crate::cli::crypto_ops::{self, Credentials, LayerDecrypter};
+use crate::cli::key_provider::KeyArgs;
+use crate::cli::{keys, utils};
+use crate::config;
+use crate::crypto::Kdf;
+use crate::error::Result;
+use clap::Parser;
+use colored::Colorize;
+use serde_yaml::Value;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Edit an encrypted config file in $EDITOR
+///
+/// The file is decrypted into a private temporary file that is removed
+/// afterwards; only values that changed are re-encrypted.
+#[derive(Parser, Debug)]
+pub struct EditArgs {
+    /// Path to the encrypted config file
+    pub file: PathBuf,
+    #[command(flatten)]
+    pub encryption: KeyArgs,
+}
+
+pub fn run(args: &EditArgs) -> Result<()> {
+    let path = &args.file;
+    let mut original: Value = serde_yaml::from_str(&fs::read_to_string(path)?)?;
+    keys::ensure_encrypted(&mut original, path)?;
+
+    let mut credentials = args.encryption.credentials(path)?;
+    let mut decrypted = original;
+    let previous = crypto_ops::decrypt_document_tracked(&mut decrypted, &mut credentials)?;
+    let plaintext = serde_yaml::to_string(&decrypted)?;
+
+    // Removed when dropped, including while unwinding from a panic
+    let mut builder = tempfile::Builder::new();
+    builder.prefix("gsm-edit-").suffix(".yaml");
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        builder.permissions(fs::Permissions::from_mode(0o600));
+    }
+    let mut temp = builder.tempfile()?;
+    temp.write_all(plaintext.as_bytes())?;
+    temp.flush()?;
+
+    let mut edited = loop {
+        open_editor(temp.path())?;
+        let content = fs::read_to_string(temp.path())?;
+        if content == plaintext {
+            println!("No changes made to '{}'", path.display());
+            return Ok(());
+        }
+        match check(path, &content, &mut credentials) {
+            Ok(document) => break document,
+            Err(e) => {
+                eprintln!("{}: {}", "Invalid config".red(), e);
+                if !confirm("Re-open the editor to fix it?")? {
+                    return Err(e);
+                }
+            }
+        }
+    };
+
+    let protection = crypto_ops::protection_for(path, &edited, &Kdf::default())?;
+    crypto_ops::reencrypt_document(&mut edited, &previous, &mut credentials, &protection)?;
+    // A crash or full disk must not leave the encrypted file truncated
+    utils::write_atomic(path, serde_yaml::to_string(&edited)?.as_bytes())?;
+    println!("Updated '{}' ✅", path.display());
+    Ok(())
+}
+
+/// Validate edited content as if it were saved at `path`, together with
+/// its base and includes
+fn check(path: &Path, content: &str, credentials: &mut Credentials) -> Result<Value> {
+    let edited: Value = serde_yaml::from_str(content)?;
+    let mut decrypter = LayerDecrypter::new(credentials);
+    let resolved = config::load_document(path, &mut |layer_path, document| {
+        if layer_path == path {
+            *document = edited.clone();
+            Ok(())
+        } else {
+            decrypter.decrypt(layer_path, document)
+        }
+    })?;
+    // Fragments only become complete configs once included elsewhere
+    if resolved.get("org").is_some() {
+        config::config_from_document(resolved)?;
+    }
+    Ok(edited)
+}
+
+/// Open a file in `$VISUAL` or `$EDITOR`, falling back to `vi`
+fn open_editor(path: &Path) -> Result<()> {
+    let editor = std::env::var("VISUAL")
+        .or_else(|_| std::env::var("EDITOR"))
+        .unwrap_or_else(|_| "vi".to_string());
+    // Run through the shell so editors configured with arguments, such as
+    // `code --wait`, work as expected
+    let status = Command::new("sh")
+        .arg("-c")
+        .arg(format!("{} \"$1\"", editor))
+        .arg("sh")
+        .arg(path)
+        .status()?;
+    if !status.success() {
+        return Err(io::Error::other(format!("editor '{}' exited with {}", editor, status)).into());
+    }
+    Ok(())
+}
+
+/// Ask a yes/no question on the terminal, defaulting to yes
+fn confirm(question: &str) -> Result<bool> {
+    print!("{} [Y/n] ", question);
+    io::stdout().flush()?;
+    let mut answer = String::new();
+    if io::stdin().read_line(&mut answer)? == 0 {
+        return Ok(false);
+    }
+    let answer = answer.trim().to_lowercase();
+    Ok(answer.is_empty() || answer == "y" || answer == "yes")
+}
@@ -1,9 +1,13 @@
-use crate::cli::{crypto_ops, utils};
-use crate::config;
+use crate::cli::crypto_ops::{self, Credentials};
+use crate::cli::kdf_args::KdfArgs;
+use crate::cli::key_provider::KeyArgs;
+use crate::cli::utils;
+use crate::config::{self, ConfigError};
+use crate::crypto::Kdf;
 use crate::error::Result;
 use clap::Parser;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 /// Encrypt a raw config file
 #[derive(Parser, Debug)]
@@ -14,6 +18,10 @@ pub struct EncryptArgs {
     /// Output file path (optional)
     #[arg(short, long)]
     pub output: Option<PathBuf>,
+    #[command(flatten)]
+    pub kdf: KdfArgs,
+    #[command(flatten)]
+    pub encryption: KeyArgs,
 }
 
 pub fn run(args: &EncryptArgs) -> Result<()> {
@@ -22,13 +30,30 @@ pub fn run(args: &EncryptArgs) -> Result<()> {
         .output
         .clone()
         .unwrap_or_else(|| utils::get_output_path(input_path, "encrypted", "yaml"));
+    let mut credentials = args.encryption.new_key_credentials(input_path)?;
+    encrypt_file(input_path, &output_path, &args.kdf.kdf()?, &mut credentials)
+}
 
-    let config = config::load_config_from_file(input_path)?;
-    let key = std::env::var("ENCRYPTION_KEY")?;
-    let encrypted_config = crypto_ops::encrypt_config(config, key.as_bytes())?;
+/// Encrypt the raw config file at `input_path` into `output_path`
+pub fn encrypt_file(
+    input_path: &Path,
+    output_path: &Path,
+    kdf: &Kdf,
+    credentials: &mut Credentials,
+) -> Result<()> {
+    // Validate the file with its base and includes, but encrypt it on its own
+    // so `extends` and `include` keep pointing at the sibling files. Fragments
+    // without an `org` only make sense once included, so they are not validated.
+    let resolved = config::load_document(input_path, &mut |_, _| Ok::<_, ConfigError>(()))?;
+    if resolved.get("org").is_some() {
+        config::config_from_document(resolved)?;
+    }
+    let mut document: serde_yaml::Value = serde_yaml::from_str(&fs::read_to_string(input_path)?)?;
+    let protection = crypto_ops::protection_for(input_path, &document, kdf)?;
+    crypto_ops::encrypt_document(&mut document, credentials, &protection)?;
 
-    let yaml = serde_yaml::to_string(&encrypted_config)?;
-    fs::write(&output_path, yaml)?;
+    let yaml = serde_yaml::to_string(&document)?;
+    fs::write(output_path, yaml)?;
     println!(
         "Encrypted '{}' to '{}' ✅",
         input_path.display(),
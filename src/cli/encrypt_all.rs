@@ -1,3 +1,5 @@
+use crate::cli::kdf_args::KdfArgs;
+use crate::cli::key_provider::KeyArgs;
 use crate::cli::{encrypt, utils};
 use crate::error::Result;
 use clap::Parser;
@@ -9,19 +11,22 @@ pub struct EncryptAllArgs {
     /// Parent input folder containing 'raw' and 'encrypted' subfolders
     #[arg(short, long)]
     pub input: PathBuf,
+    #[command(flatten)]
+    pub kdf: KdfArgs,
+    #[command(flatten)]
+    pub encryption: KeyArgs,
 }
 
 pub fn run(args: &EncryptAllArgs) -> Result<()> {
+    // One set of credentials, so a prompted key is only asked for once
+    let mut credentials = args.encryption.new_key_credentials(&args.input)?;
+    let kdf = args.kdf.kdf()?;
     utils::process_directory(
         &args.input,
         "raw",
         "encrypted",
         |input_path, output_path| {
-            let encrypt_args = encrypt::EncryptArgs {
-                file: input_path.to_path_buf(),
-                output: Some(output_path.to_path_buf()),
-            };
-            encrypt::run(&encrypt_args)
+            encrypt::encrypt_file(input_path, output_path, &kdf, &mut credentials)
         },
     )
 }
@@ -0,0 +1,51 @@
+use crate::cli::crypto_ops::{self, FileCipher};
+use crate::cli::key_provider::KeyArgs;
+use crate::cli::keys::SecretKey;
+use crate::config::ConfigError;
+use crate::error::Result;
+use clap::Parser;
+use serde_yaml::Value;
+use std::fs;
+use std::path::PathBuf;
+
+/// Print the decrypted value of one secret
+#[derive(Parser, Debug)]
+pub struct GetArgs {
+    /// Path to the encrypted config file
+    #[arg(short, long)]
+    pub file: PathBuf,
+    /// Secret key, such as `API_KEY` or `environments.production.env.DEPLOY_TOKEN`
+    pub key: String,
+    #[command(flatten)]
+    pub encryption: KeyArgs,
+}
+
+pub fn run(args: &GetArgs) -> Result<()> {
+    let key = SecretKey::parse(&args.key)?;
+    let mut document: Value = serde_yaml::from_str(&fs::read_to_string(&args.file)?)?;
+    // Plaintext values of an encrypted file are only trusted once its MAC checks out
+    let cipher = if crypto_ops::is_encrypted(&mut document) {
+        Some(FileCipher::open(
+            &document,
+            &mut args.encryption.credentials(&args.file)?,
+        )?)
+    } else {
+        None
+    };
+    let value = match (key.get(&document), &cipher) {
+        (Some(Value::String(plaintext)), _) => plaintext.clone(),
+        (Some(secret), Some(cipher)) if secret.is_mapping() => {
+            cipher.decrypt(&key.path(), secret)?
+        }
+        _ => {
+            return Err(ConfigError::Invalid(format!(
+                "'{}' is not set in '{}'",
+                key,
+                args.file.display()
+            ))
+            .into());
+        }
+    };
+    println!("{}", value);
+    Ok(())
+}
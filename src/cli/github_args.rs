@@ -0,0 +1,81 @@
+use crate::error::Result;
+use crate::github::{DEFAULT_CONCURRENCY, GithubApp, GithubClient, GithubError, RetryPolicy};
+use clap::Args;
+use std::fs;
+use std::path::PathBuf;
+use std::time::Duration;
+
+const DEFAULT_API_URL: &str = "https://api.github.com";
+
+/// GitHub connection options shared by commands that talk to the API
+#[derive(Args, Debug)]
+pub struct GithubArgs {
+    /// GitHub API base URL (for GitHub Enterprise)
+    #[arg(long, default_value = DEFAULT_API_URL)]
+    pub api_url: String,
+    /// Maximum number of GitHub API requests in flight at once
+    #[arg(long, default_value_t = DEFAULT_CONCURRENCY)]
+    pub concurrency: usize,
+    /// Retries for rate-limited or transiently failing requests (0 disables retrying)
+    #[arg(long, default_value_t = RetryPolicy::default().max_retries)]
+    pub max_retries: u32,
+    /// Initial retry backoff in milliseconds, doubled on every attempt
+    #[arg(long, value_name = "MS", default_value_t = 1000)]
+    pub retry_base_delay: u64,
+    /// Maximum retry delay in milliseconds; longer waits requested by GitHub fail instead
+    #[arg(long, value_name = "MS", default_value_t = 60_000)]
+    pub retry_max_delay: u64,
+    /// GitHub App ID; authenticates as the app instead of with `GITHUB_TOKEN`
+    #[arg(long, env = "GITHUB_APP_ID")]
+    pub app_id: Option<String>,
+    /// Path to the GitHub App private key (PEM); defaults to the contents of
+    /// `GITHUB_APP_PRIVATE_KEY`
+    #[arg(long, env = "GITHUB_APP_PRIVATE_KEY_PATH", value_name = "PATH")]
+    pub app_private_key: Option<PathBuf>,
+    /// Installation ID of the app; looked up from the org when omitted
+    #[arg(long, env = "GITHUB_APP_INSTALLATION_ID")]
+    pub app_installation_id: Option<u64>,
+}
+
+impl GithubArgs {
+    /// Create a GitHub client for `org`, authenticated as a GitHub App when
+    /// an app ID is given and with `GITHUB_TOKEN` otherwise
+    pub fn client(&self, org: &str) -> Result<GithubClient> {
+        // Create GitHub client with custom API URL support
+        let api_url = if self.api_url == DEFAULT_API_URL {
+            None
+        } else {
+            Some(self.api_url.clone())
+        };
+        let retry_policy = RetryPolicy {
+            max_retries: self.max_retries,
+            base_delay: Duration::from_millis(self.retry_base_delay),
+            max_delay: Duration::from_millis(self.retry_max_delay),
+        };
+        let client = match &self.app_id {
+            Some(app_id) => {
+                let private_key = match &self.app_private_key {
+                    Some(path) => fs::read(path)?,
+                    None => std::env::var("GITHUB_APP_PRIVATE_KEY")
+                        .map_err(|_| {
+                            GithubError::AppAuth(
+                                "set --app-private-key or GITHUB_APP_PRIVATE_KEY".to_string(),
+                            )
+                        })?
+                        .into_bytes(),
+                };
+                let app = GithubApp::new(
+                    app_id.clone(),
+                    &private_key,
+                    org.to_string(),
+                    self.app_installation_id,
+                )?;
+                GithubClient::from_app(app, api_url)
+            }
+            None => GithubClient::new(std::env::var("GITHUB_TOKEN")?, api_url),
+        };
+        Ok(client
+            .with_concurrency(self.concurrency)
+            .with_retry_policy(retry_policy))
+    }
+}
@@ -0,0 +1,47 @@
+use crate::crypto::{self, Kdf};
+use crate::error::Result;
+use clap::{Args, ValueEnum};
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum KdfName {
+    Argon2id,
+    Pbkdf2Sha256,
+}
+
+/// Key derivation options for commands that write new encrypted files
+#[derive(Args, Debug, Clone)]
+pub struct KdfArgs {
+    /// Key derivation function, recorded in the file so it can be decrypted later
+    #[arg(long, value_enum, default_value_t = KdfName::Argon2id)]
+    pub kdf: KdfName,
+    /// Argon2id memory cost in KiB (at least 19456)
+    #[arg(long, value_name = "KIB", default_value_t = crypto::ARGON2_MEMORY_KIB)]
+    pub argon2_memory: u32,
+    /// Argon2id number of passes over the memory (at least 2)
+    #[arg(long, default_value_t = crypto::ARGON2_TIME)]
+    pub argon2_time: u32,
+    /// Argon2id degree of parallelism
+    #[arg(long, default_value_t = crypto::ARGON2_PARALLELISM)]
+    pub argon2_parallelism: u32,
+    /// PBKDF2-SHA256 iterations (at least the default)
+    #[arg(long, default_value_t = crypto::PBKDF2_ITER)]
+    pub pbkdf2_iterations: u32,
+}
+
+impl KdfArgs {
+    /// The chosen KDF, refusing parameters weaker than gsm's minimums
+    pub fn kdf(&self) -> Result<Kdf> {
+        let kdf = match self.kdf {
+            KdfName::Argon2id => Kdf::Argon2id {
+                memory_kib: self.argon2_memory,
+                time: self.argon2_time,
+                parallelism: self.argon2_parallelism,
+            },
+            KdfName::Pbkdf2Sha256 => Kdf::Pbkdf2Sha256 {
+                iterations: self.pbkdf2_iterations,
+            },
+        };
+        kdf.check_strength()?;
+        Ok(kdf)
+    }
+}
@@ -0,0 +1,307 @@
+// Where the encryption key comes from: the environment, a file, a prompt, a
+// command or the local keyring file
+
+use crate::cli::crypto_ops::Credentials;
+use crate::cli::project::{PROJECT_FILE, ProjectFile, USER_CONFIG, UserConfig};
+use crate::cli::utils;
+use crate::config::ConfigError;
+use crate::crypto::CryptoError;
+use crate::error::Result;
+use clap::Args;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+
+/// What the terminal asks for when the key source is `prompt`
+const PROMPT_LABEL: &str = "Encryption key";
+
+/// Source of an encryption key
+pub trait KeyProvider {
+    fn key(&self) -> Result<Vec<u8>>;
+}
+
+/// A key in an environment variable
+pub struct EnvVar {
+    pub name: String,
+}
+
+impl KeyProvider for EnvVar {
+    fn key(&self) -> Result<Vec<u8>> {
+        match std::env::var(&self.name) {
+            Ok(key) if !key.is_empty() => Ok(key.into_bytes()),
+            _ => Err(CryptoError::KeyError(format!("{} is not set", self.name)).into()),
+        }
+    }
+}
+
+/// A key in a file that only its owner can read
+pub struct KeyFile {
+    pub path: PathBuf,
+}
+
+impl KeyProvider for KeyFile {
+    fn key(&self) -> Result<Vec<u8>> {
+        let content = utils::read_private(&self.path)?;
+        non_empty(trim_newline(&content), &self.path.display().to_string())
+    }
+}
+
+/// A key typed at the terminal, without echo
+pub struct Prompt {
+    pub label: String,
+    /// Ask twice, for keys that are about to encrypt files
+    pub confirm: bool,
+}
+
+impl KeyProvider for Prompt {
+    fn key(&self) -> Result<Vec<u8>> {
+        let ask = |label: String| {
+            rpassword::prompt_password(label).map_err(|e| {
+                CryptoError::KeyError(format!("cannot prompt for the key on a terminal: {}", e))
+            })
+        };
+        let key = ask(format!("{}: ", self.label))?;
+        if self.confirm && ask(format!("Repeat {}: ", self.label.to_lowercase()))? != key {
+            return Err(CryptoError::KeyError("the keys do not match".to_string()).into());
+        }
+        non_empty(&key, "the prompt")
+    }
+}
+
+/// A key printed by a command, such as a password manager's CLI
+pub struct KeyCommand {
+    pub command: String,
+}
+
+impl KeyProvider for KeyCommand {
+    fn key(&self) -> Result<Vec<u8>> {
+        let output = Command::new("sh")
+            .arg("-c")
+            .arg(&self.command)
+            .stdin(Stdio::inherit())
+            .stderr(Stdio::inherit())
+            .output()?;
+        if !output.status.success() {
+            return Err(CryptoError::KeyError(format!(
+                "key command '{}' exited with {}",
+                self.command, output.status
+            ))
+            .into());
+        }
+        let key = String::from_utf8(output.stdout).map_err(|_| {
+            CryptoError::KeyError(format!(
+                "key command '{}' printed invalid UTF-8",
+                self.command
+            ))
+        })?;
+        non_empty(
+            trim_newline(&key),
+            &format!("key command '{}'", self.command),
+        )
+    }
+}
+
+/// A named key in the local keyring file, a YAML mapping of names to keys
+pub struct Keyring {
+    pub path: PathBuf,
+    pub name: String,
+}
+
+impl Keyring {
+    /// Keyring file from `GSM_KEYRING`, defaulting to `~/.gsm/keyring`
+    pub fn default_path() -> Result<PathBuf> {
+        match std::env::var_os("GSM_KEYRING") {
+            Some(path) => Ok(PathBuf::from(path)),
+            None => Ok(utils::home_dir()?.join(".gsm").join("keyring")),
+        }
+    }
+}
+
+impl KeyProvider for Keyring {
+    fn key(&self) -> Result<Vec<u8>> {
+        let content = utils::read_private(&self.path)?;
+        let keys: BTreeMap<String, String> = serde_yaml::from_str(&content).map_err(|e| {
+            CryptoError::KeyError(format!("invalid keyring '{}': {}", self.path.display(), e))
+        })?;
+        let key = keys.get(&self.name).ok_or_else(|| {
+            CryptoError::KeyError(format!(
+                "no key named '{}' in the keyring '{}'",
+                self.name,
+                self.path.display()
+            ))
+        })?;
+        non_empty(key, &format!("keyring entry '{}'", self.name))
+    }
+}
+
+/// A key source as written on the command line or in a `.gsm` file:
+/// `env:VAR`, `file:PATH`, `prompt`, `command:CMD` or `keyring:NAME`
+#[derive(Debug, Clone, PartialEq)]
+pub enum KeySource {
+    Env(String),
+    File(PathBuf),
+    Prompt,
+    Command(String),
+    Keyring(String),
+}
+
+impl KeySource {
+    pub fn provider(&self) -> Result<Box<dyn KeyProvider>> {
+        Ok(match self {
+            KeySource::Env(name) => Box::new(EnvVar { name: name.clone() }),
+            KeySource::File(path) => Box::new(KeyFile { path: path.clone() }),
+            KeySource::Prompt => Box::new(Prompt {
+                label: PROMPT_LABEL.to_string(),
+                confirm: false,
+            }),
+            KeySource::Command(command) => Box::new(KeyCommand {
+                command: command.clone(),
+            }),
+            KeySource::Keyring(name) => Box::new(Keyring {
+                path: Keyring::default_path()?,
+                name: name.clone(),
+            }),
+        })
+    }
+}
+
+impl Default for KeySource {
+    fn default() -> Self {
+        KeySource::Env("ENCRYPTION_KEY".to_string())
+    }
+}
+
+impl FromStr for KeySource {
+    type Err = CryptoError;
+
+    fn from_str(spec: &str) -> std::result::Result<Self, Self::Err> {
+        let source = match spec.split_once(':') {
+            None if spec == "prompt" => KeySource::Prompt,
+            Some(("env", name)) if !name.is_empty() => KeySource::Env(name.to_string()),
+            Some(("file", path)) if !path.is_empty() => KeySource::File(expand_home(path)?),
+            Some(("command", command)) if !command.is_empty() => {
+                KeySource::Command(command.to_string())
+            }
+            Some(("keyring", name)) if !name.is_empty() => KeySource::Keyring(name.to_string()),
+            _ => {
+                return Err(CryptoError::KeyError(format!(
+                    "invalid key source '{}': expected env:VAR, file:PATH, prompt, \
+                     command:CMD or keyring:NAME",
+                    spec
+                )));
+            }
+        };
+        Ok(source)
+    }
+}
+
+impl fmt::Display for KeySource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KeySource::Env(name) => write!(f, "env:{}", name),
+            KeySource::File(path) => write!(f, "file:{}", path.display()),
+            KeySource::Prompt => write!(f, "prompt"),
+            KeySource::Command(command) => write!(f, "command:{}", command),
+            KeySource::Keyring(name) => write!(f, "keyring:{}", name),
+        }
+    }
+}
+
+/// Where to read the encryption key from
+#[derive(Args, Debug, Clone, Default)]
+pub struct KeyArgs {
+    /// Read the key from `env:VAR`, `file:PATH`, `prompt`, `command:CMD` or
+    /// `keyring:NAME` [default: `key_source` in the `.gsm` file, then in
+    /// ~/.gsm/config, or env:ENCRYPTION_KEY]
+    #[arg(long, value_name = "SOURCE")]
+    pub key_source: Option<KeySource>,
+    /// Vault address for files wrapped by Vault Transit [default: VAULT_ADDR,
+    /// then `vault.address` in ~/.gsm/config]
+    #[arg(long, value_name = "URL")]
+    pub vault_addr: Option<String>,
+    /// Allow a plain `http://` Vault address, which sends the Vault token
+    /// unencrypted
+    #[arg(long)]
+    pub vault_allow_http: bool,
+}
+
+impl KeyArgs {
+    /// The key source for config files at `path`: the flag, or else the
+    /// nearest `.gsm` file's `key_source`, or else the user config's
+    ///
+    /// A `.gsm` file arrives with the repository, so it may not run commands.
+    pub fn source(&self, path: &Path) -> Result<KeySource> {
+        if let Some(source) = &self.key_source {
+            return Ok(source.clone());
+        }
+        if let Some(spec) = ProjectFile::find(path)?.and_then(|project| project.key_source) {
+            let source: KeySource = spec.parse()?;
+            if let KeySource::Command(_) = source {
+                return Err(ConfigError::Invalid(format!(
+                    "'{}' files cannot use a command: key source; pass --key-source or set \
+                     key_source in '{}'",
+                    PROJECT_FILE, USER_CONFIG
+                ))
+                .into());
+            }
+            return Ok(source);
+        }
+        match UserConfig::load()?.key_source {
+            Some(spec) => Ok(spec.parse()?),
+            None => Ok(KeySource::default()),
+        }
+    }
+
+    /// Credentials that read the key for `path` once a file needs it
+    pub fn credentials(&self, path: &Path) -> Result<Credentials> {
+        Ok(self.credentials_with(self.source(path)?.provider()?))
+    }
+
+    /// Like [`KeyArgs::credentials`], for commands that derive a new file key
+    /// from the key: a prompted key is asked for twice, since a typo would
+    /// encrypt the file under a key nobody knows
+    pub fn new_key_credentials(&self, path: &Path) -> Result<Credentials> {
+        let provider: Box<dyn KeyProvider> = match self.source(path)? {
+            KeySource::Prompt => Box::new(Prompt {
+                label: PROMPT_LABEL.to_string(),
+                confirm: true,
+            }),
+            source => source.provider()?,
+        };
+        Ok(self.credentials_with(provider))
+    }
+
+    fn credentials_with(&self, provider: Box<dyn KeyProvider>) -> Credentials {
+        Credentials {
+            provider: Some(provider),
+            vault_address: self.vault_addr.clone(),
+            vault_allow_http: self.vault_allow_http,
+            ..Credentials::default()
+        }
+    }
+}
+
+fn trim_newline(key: &str) -> &str {
+    let key = key.strip_suffix('\n').unwrap_or(key);
+    key.strip_suffix('\r').unwrap_or(key)
+}
+
+fn non_empty(key: &str, source: &str) -> Result<Vec<u8>> {
+    if key.is_empty() {
+        return Err(CryptoError::KeyError(format!("the key from {} is empty", source)).into());
+    }
+    Ok(key.as_bytes().to_vec())
+}
+
+/// Expand a leading `~/`, which shells leave alone inside `--flag=~/path`
+/// and which `.gsm` files use for paths in the home directory
+fn expand_home(path: &str) -> std::result::Result<PathBuf, CryptoError> {
+    match path.strip_prefix("~/") {
+        Some(rest) => Ok(utils::home_dir()
+            .map_err(|e| CryptoError::KeyError(e.to_string()))?
+            .join(rest)),
+        None => Ok(PathBuf::from(path)),
+    }
+}
@@ -0,0 +1,65 @@
+use crate::cli::crypto_ops;
+use crate::config::ConfigError;
+use crate::crypto::Identity;
+use crate::error::Result;
+use base64::{Engine as _, engine::general_purpose};
+use clap::Parser;
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+
+/// Generate an X25519 identity for files shared with recipients
+///
+/// The secret key is written to a file only its owner can read; add the
+/// printed public key to a file's `recipients` or to the project's `.gsm`
+/// file, then run `gsm migrate` to grant access.
+#[derive(Parser, Debug)]
+pub struct KeygenArgs {
+    /// Where to write the identity [default: $GSM_IDENTITY or ~/.gsm/identity]
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+    /// Replace an existing identity
+    #[arg(long)]
+    pub force: bool,
+}
+
+pub fn run(args: &KeygenArgs) -> Result<()> {
+    let path = match &args.output {
+        Some(path) => path.clone(),
+        None => crypto_ops::identity_path()?,
+    };
+    if path.exists() && !args.force {
+        return Err(ConfigError::Invalid(format!(
+            "'{}' already exists; pass --force to replace it",
+            path.display()
+        ))
+        .into());
+    }
+    if path.exists() {
+        // Recreated below, so the new file gets owner-only permissions
+        fs::remove_file(&path)?;
+    } else if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let identity = Identity::generate()?;
+    let public_key = general_purpose::STANDARD.encode(identity.public_key());
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options.open(&path)?;
+    writeln!(file, "# public key: {}", public_key)?;
+    writeln!(
+        file,
+        "{}",
+        general_purpose::STANDARD.encode(identity.secret_key())
+    )?;
+
+    println!("Wrote identity to '{}' ✅", path.display());
+    println!("Public key: {}", public_key);
+    Ok(())
+}
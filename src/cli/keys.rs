@@ -0,0 +1,123 @@
+use crate::config::{self, ConfigError};
+use crate::error::Result;
+use serde_yaml::{Mapping, Value};
+use std::fmt;
+
+/// A secret addressed on the command line
+///
+/// Accepted forms are `NAME` or `env.NAME`, `org_secrets.NAME`,
+/// `environments.ENVIRONMENT.env.NAME` and `overrides.REPO.env.NAME`.
+#[derive(Debug)]
+pub struct SecretKey {
+    /// Path of the entry that `unset` removes
+    entry: Vec<String>,
+    /// Path of the encrypted value itself
+    value: Vec<String>,
+}
+
+impl SecretKey {
+    pub fn parse(key: &str) -> Result<SecretKey> {
+        let parts: Vec<String> = key.split('.').map(str::to_string).collect();
+        let parts_ref: Vec<&str> = parts.iter().map(String::as_str).collect();
+        let (entry, value) = match parts_ref.as_slice() {
+            [name] => (vec!["env", name], vec!["env", name]),
+            ["env", name] => (vec!["env", name], vec!["env", name]),
+            ["org_secrets", name] | ["org_secrets", name, "value"] => (
+                vec!["org_secrets", name],
+                vec!["org_secrets", name, "value"],
+            ),
+            [section @ ("environments" | "overrides"), scope, "env", name] => (
+                vec![section, scope, "env", name],
+                vec![section, scope, "env", name],
+            ),
+            _ => {
+                return Err(ConfigError::Invalid(format!(
+                    "'{}' is not a secret key; use NAME, env.NAME, org_secrets.NAME, \
+                     environments.ENVIRONMENT.env.NAME or overrides.REPO.env.NAME",
+                    key
+                ))
+                .into());
+            }
+        };
+        let owned = |path: Vec<&str>| path.into_iter().map(str::to_string).collect();
+        Ok(SecretKey {
+            entry: owned(entry),
+            value: owned(value),
+        })
+    }
+
+    /// Key path of the encrypted value, as associated data binds it
+    pub fn path(&self) -> String {
+        self.value.join(".")
+    }
+
+    /// The secret value in a config document, if present
+    pub fn get<'a>(&self, document: &'a Value) -> Option<&'a Value> {
+        self.value
+            .iter()
+            .try_fold(document, |node, part| node.get(part.as_str()))
+    }
+
+    /// Store a secret value, creating the mappings leading to it
+    pub fn set(&self, document: &mut Value, secret: Value) -> Result<()> {
+        let mut node = document;
+        for part in &self.value {
+            if node.is_null() {
+                *node = Value::Mapping(Mapping::new());
+            }
+            let Some(mapping) = node.as_mapping_mut() else {
+                return Err(self.not_a_mapping());
+            };
+            node = mapping
+                .entry(Value::String(part.clone()))
+                .or_insert(Value::Null);
+        }
+        *node = secret;
+        Ok(())
+    }
+
+    /// Remove the secret's entry, returning whether it existed
+    pub fn unset(&self, document: &mut Value) -> Result<bool> {
+        let (last, parents) = self.entry.split_last().expect("keys are never empty");
+        let mut node = document;
+        for part in parents {
+            match node.get_mut(part.as_str()) {
+                Some(child) => node = child,
+                None => return Ok(false),
+            }
+        }
+        let Some(mapping) = node.as_mapping_mut() else {
+            return Err(self.not_a_mapping());
+        };
+        Ok(mapping.remove(last.as_str()).is_some())
+    }
+
+    fn not_a_mapping(&self) -> crate::error::GsmError {
+        ConfigError::Invalid(format!(
+            "cannot store '{}': a parent is not a mapping",
+            self
+        ))
+        .into()
+    }
+}
+
+impl fmt::Display for SecretKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.entry.join("."))
+    }
+}
+
+/// Refuse to mix encrypted values into a file that still holds plaintext secrets
+pub fn ensure_encrypted(document: &mut Value, file: &std::path::Path) -> Result<()> {
+    if config::secret_values_mut(document)
+        .iter()
+        .any(|(_, secret)| secret.is_string())
+    {
+        return Err(ConfigError::Invalid(format!(
+            "'{}' contains plaintext secrets; encrypt it first",
+            file.display()
+        ))
+        .into());
+    }
+    Ok(())
+}
@@ -1,11 +1,27 @@
 // CLI module (command-line interface)
 
+pub mod apply;
 pub mod crypto_ops;
 pub mod decrypt;
 pub mod decrypt_all;
+pub mod edit;
 pub mod encrypt;
 pub mod encrypt_all;
+pub mod get;
+pub mod github_args;
+pub mod kdf_args;
+pub mod key_provider;
+pub mod keygen;
+pub mod keys;
+pub mod migrate;
+pub mod plan;
+pub mod project;
 pub mod push;
+pub mod rekey;
+pub mod repositories;
+pub mod set;
+pub mod sync;
+pub mod unset;
 pub mod utils;
 pub mod validate;
 
@@ -31,6 +47,26 @@ pub enum Commands {
     EncryptAll(encrypt_all::EncryptAllArgs),
     /// Decrypt all encrypted config files
     DecryptAll(decrypt_all::DecryptAllArgs),
+    /// Edit an encrypted config file in $EDITOR
+    Edit(edit::EditArgs),
+    /// Print the decrypted value of one secret
+    Get(get::GetArgs),
+    /// Encrypt and store one secret read from stdin or a file
+    Set(set::SetArgs),
+    /// Remove one secret from a config file
+    Unset(unset::UnsetArgs),
+    /// Upgrade encrypted config files to the newest format
+    Migrate(migrate::MigrateArgs),
+    /// Generate an identity for files shared with recipients
+    Keygen(keygen::KeygenArgs),
+    /// Re-encrypt config files under a new encryption key
+    Rekey(rekey::RekeyArgs),
     /// Push secrets to GitHub repositories
     Push(push::PushArgs),
+    /// Push secrets and prune those not declared in the config
+    Sync(sync::SyncArgs),
+    /// Preview the changes a push would make
+    Plan(plan::PlanArgs),
+    /// Apply a fresh or saved plan
+    Apply(apply::ApplyArgs),
 }
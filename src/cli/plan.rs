@@ -0,0 +1,80 @@
+use crate::cli::github_args::GithubArgs;
+use crate::cli::key_provider::KeyArgs;
+use crate::cli::{crypto_ops, repositories};
+use crate::config::{self, Config};
+use crate::error::Result;
+use crate::plan::{self, State};
+use clap::{Args, Parser};
+use glob::Pattern;
+use std::path::PathBuf;
+
+/// Preview the changes a push would make
+#[derive(Parser, Debug)]
+pub struct PlanArgs {
+    /// Path to a raw or encrypted config file
+    #[arg(short, long)]
+    pub file: PathBuf,
+    /// Save the plan to this file for a later `gsm apply --plan`
+    #[arg(short, long)]
+    pub out: Option<PathBuf>,
+    #[command(flatten)]
+    pub options: PlanOptions,
+    #[command(flatten)]
+    pub github: GithubArgs,
+    #[command(flatten)]
+    pub encryption: KeyArgs,
+}
+
+/// Options that shape how a plan is computed
+#[derive(Args, Debug)]
+pub struct PlanOptions {
+    /// State file recording fingerprints of the values gsm has pushed; their
+    /// key is kept next to it with a `.key` suffix
+    #[arg(long, default_value = ".gsm-state.json")]
+    pub state: PathBuf,
+    /// Plan deletion of secrets not declared in the config
+    #[arg(long)]
+    pub prune: bool,
+    /// Secret name pattern that must never be deleted (repeatable)
+    #[arg(long = "protect", value_name = "PATTERN")]
+    pub protect: Vec<String>,
+}
+
+impl PlanOptions {
+    /// Protected patterns from the config plus those given on the command line
+    pub fn protected(&self, config: &Config) -> Result<Vec<Pattern>> {
+        let mut patterns = config.protected.clone();
+        patterns.extend(self.protect.iter().cloned());
+        Ok(config::compile_patterns(&patterns)?)
+    }
+}
+
+pub async fn run(args: &PlanArgs) -> Result<()> {
+    let mut credentials = args.encryption.credentials(&args.file)?;
+    let mut config = crypto_ops::load_config(&args.file, &mut credentials)?;
+    let protected = args.options.protected(&config)?;
+    let state = State::load_or_init(&args.options.state)?;
+    if !args.options.state.exists() {
+        // Persist the salt now so a saved plan can be verified at apply time
+        state.save(&args.options.state)?;
+    }
+    let github_client = args.github.client(&config.org)?;
+    repositories::resolve(&github_client, &mut config).await?;
+
+    let plan = plan::build_plan(
+        &github_client,
+        &config,
+        &args.file,
+        &state,
+        args.options.prune,
+        &protected,
+    )
+    .await?;
+    plan.print();
+
+    if let Some(out) = &args.out {
+        plan.save(out)?;
+        println!("Saved plan to '{}'", out.display());
+    }
+    Ok(())
+}
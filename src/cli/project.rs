@@ -0,0 +1,117 @@
+use crate::cli::utils;
+use crate::config::ConfigError;
+use crate::crypto::TransitKey;
+use crate::error::Result;
+use serde::Deserialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Name of the project settings file, looked up from a config file's
+/// directory upwards
+pub const PROJECT_FILE: &str = ".gsm";
+
+/// Where the user's own settings live, for messages
+pub const USER_CONFIG: &str = "~/.gsm/config";
+
+/// Settings shared by the config files of a project
+#[derive(Debug, Default, Deserialize)]
+pub struct ProjectFile {
+    /// Public keys that files without their own `recipients` are encrypted to
+    #[serde(default)]
+    pub recipients: Vec<String>,
+    /// Where to read the encryption key from, such as `file:~/.gsm/key`;
+    /// `command:` sources are refused
+    pub key_source: Option<String>,
+    /// Vault Transit key that wraps the key of each new file; the Vault
+    /// address is the user's to choose and may not be set here
+    pub transit: Option<TransitKey>,
+}
+
+impl ProjectFile {
+    /// The `.gsm` file closest to the config file or directory at `path`,
+    /// if any
+    pub fn find(path: &Path) -> Result<Option<ProjectFile>> {
+        let path = std::path::absolute(path)?;
+        let skip = if path.is_dir() { 0 } else { 1 };
+        for dir in path.ancestors().skip(skip) {
+            let candidate = dir.join(PROJECT_FILE);
+            if !candidate.is_file() {
+                continue;
+            }
+            let content = fs::read_to_string(&candidate)?;
+            let invalid = |e: serde_yaml::Error| {
+                ConfigError::Invalid(format!("invalid '{}': {}", candidate.display(), e))
+            };
+            let project: serde_yaml::Value = serde_yaml::from_str(&content).map_err(invalid)?;
+            if project
+                .get("transit")
+                .and_then(|transit| transit.get("address"))
+                .is_some()
+            {
+                return Err(ConfigError::Invalid(format!(
+                    "'{}' cannot name the Vault address, since the Vault token is sent there; \
+                     set VAULT_ADDR, --vault-addr or vault.address in '{}'",
+                    candidate.display(),
+                    USER_CONFIG
+                ))
+                .into());
+            }
+            return Ok(Some(serde_yaml::from_value(project).map_err(invalid)?));
+        }
+        Ok(None)
+    }
+}
+
+/// Personal settings in `~/.gsm/config`
+///
+/// Unlike a `.gsm` file, which comes with whatever repository is checked
+/// out, this file is the user's own, so it may name a `command:` key source.
+#[derive(Debug, Default, Deserialize)]
+pub struct UserConfig {
+    /// Where to read the encryption key from when neither the flag nor a
+    /// `.gsm` file says
+    pub key_source: Option<String>,
+    /// Vault to use for files wrapped by Vault Transit
+    #[serde(default)]
+    pub vault: VaultSettings,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct VaultSettings {
+    /// Used when neither `--vault-addr` nor `VAULT_ADDR` is set
+    pub address: Option<String>,
+    /// Allow a plain `http://` address, as `--vault-allow-http` does
+    #[serde(default)]
+    pub allow_http: bool,
+}
+
+impl UserConfig {
+    pub fn path() -> Result<PathBuf> {
+        Ok(utils::home_dir()?.join(".gsm").join("config"))
+    }
+
+    /// Load the user config, if there is one, refusing a file that someone
+    /// other than the owner of the home directory could have written
+    pub fn load() -> Result<UserConfig> {
+        let path = Self::path()?;
+        if !path.is_file() {
+            return Ok(UserConfig::default());
+        }
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::{MetadataExt, PermissionsExt};
+            let metadata = fs::metadata(&path)?;
+            let home = fs::metadata(utils::home_dir()?)?;
+            if metadata.uid() != home.uid() || metadata.permissions().mode() & 0o022 != 0 {
+                return Err(ConfigError::Invalid(format!(
+                    "'{}' must be owned by you and writable by no one else",
+                    path.display()
+                ))
+                .into());
+            }
+        }
+        let content = fs::read_to_string(&path)?;
+        Ok(serde_yaml::from_str(&content)
+            .map_err(|e| ConfigError::Invalid(format!("invalid '{}': {}", path.display(), e)))?)
+    }
+}
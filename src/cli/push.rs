@@ -1,49 +1,755 @@
-use crate::config;
-use crate::error::Result;
-use crate::github::{GithubClient, encrypt_github_secret};
+use crate::cli::github_args::GithubArgs;
+use crate::cli::key_provider::KeyArgs;
+use crate::cli::{crypto_ops, repositories};
+use crate::config::{self, Config, ConfigError, Environment, SecretTarget, Visibility};
+use crate::error::{GsmError, Result};
+use crate::github::{
+    GithubClient, OrgSecretBody, VariableBody, VariableScope, encrypt_github_secret,
+};
+use crate::plan;
 use clap::Parser;
-use std::path::PathBuf;
+use colored::Colorize;
+use futures::future::{join_all, try_join_all};
+use futures::stream::{self, StreamExt};
+use serde::Serialize;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
 
 /// Push secrets to GitHub repositories
 #[derive(Parser, Debug)]
 pub struct PushArgs {
-    /// Path to the raw config file
+    /// Path to a raw or encrypted config file, or a directory of them
+    ///
+    /// Encrypted files are decrypted in memory only. In a directory, files
+    /// that other files extend or include are not pushed on their own.
     #[arg(short, long)]
     pub file: PathBuf,
-    /// GitHub API base URL (for GitHub Enterprise)
-    #[arg(long, default_value = "https://api.github.com")]
-    pub api_url: String,
+    /// Keep pushing past failed repositories and secrets, reporting them at the end
+    #[arg(long)]
+    pub continue_on_error: bool,
+    /// Write the per-repository result report as JSON
+    #[arg(long, value_name = "PATH", requires = "continue_on_error")]
+    pub report_json: Option<PathBuf>,
+    #[command(flatten)]
+    pub github: GithubArgs,
+    #[command(flatten)]
+    pub encryption: KeyArgs,
 }
 
-pub async fn run(args: &PushArgs) -> Result<()> {
-    let config = config::load_config_from_file(&args.file)?;
-    let token = std::env::var("GITHUB_TOKEN")?;
+/// Exit code of a push that completed with some failed repositories or secrets
+pub const EXIT_PARTIAL_FAILURE: u8 = 3;
 
-    // Create GitHub client with custom API URL support
-    let api_url = if args.api_url == "https://api.github.com" {
-        None
-    } else {
-        Some(args.api_url.clone())
+pub async fn run(args: &PushArgs) -> Result<ExitCode> {
+    let configs = load_configs(&args.file, &args.encryption)?;
+    let count = configs.len();
+
+    let mut report = PushReport::default();
+    for (file, mut config) in configs {
+        if count > 1 {
+            println!("{} {}", "==>".bold(), file.display());
+        }
+        let pushed = async {
+            let github_client = args.github.client(&config.org)?;
+            repositories::resolve(&github_client, &mut config).await?;
+            push_config(&github_client, &config, args.continue_on_error).await
+        }
+        .await;
+        let file_report = match pushed {
+            Ok(file_report) => file_report,
+            Err(error) if args.continue_on_error => {
+                println!("- {}: {} ({})", file.display(), "failed".red(), error);
+                report.merge(PushReport::file_failed(&file, error));
+                continue;
+            }
+            Err(error) => return Err(error),
+        };
+        let summary = &file_report.summary;
+        println!(
+            "Pushed {} secret(s) and {} variable(s) ({} unchanged) across {} repositories",
+            summary.secrets,
+            summary.variables_changed,
+            summary.variables_unchanged,
+            summary.repositories
+        );
+        report.merge(file_report);
+    }
+
+    if args.continue_on_error {
+        report.print_table();
+    }
+    if let Some(path) = &args.report_json {
+        fs::write(path, serde_json::to_string_pretty(&report)?)?;
+        println!("Report written to {}", path.display());
+    }
+
+    if report.summary.failed > 0 {
+        println!("{} operation(s) failed", report.summary.failed);
+        return Ok(ExitCode::from(EXIT_PARTIAL_FAILURE));
+    }
+    println!("All secrets pushed successfully!");
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Decrypt and validate every config file to push for a path, so that
+/// nothing is pushed when any of them is broken
+pub fn load_configs(path: &Path, encryption: &KeyArgs) -> Result<Vec<(PathBuf, Config)>> {
+    let mut credentials = encryption.credentials(path)?;
+    config_files(path)?
+        .into_iter()
+        .map(|file| {
+            let config = crypto_ops::load_config(&file, &mut credentials)?;
+            Ok((file, config))
+        })
+        .collect()
+}
+
+/// The config files to push for a path: the file itself, or the `.yaml`
+/// files of a directory that no other file there extends or includes
+fn config_files(path: &Path) -> Result<Vec<PathBuf>> {
+    if !path.is_dir() {
+        return Ok(vec![path.to_path_buf()]);
+    }
+    let mut files = Vec::new();
+    for entry in fs::read_dir(path)? {
+        let file = entry?.path();
+        if file.is_file() && file.extension().is_some_and(|e| e == "yaml" || e == "yml") {
+            files.push(file);
+        }
+    }
+    files.sort();
+
+    let mut referenced = HashSet::new();
+    for file in &files {
+        for reference in config::references(file)? {
+            if let Ok(reference) = fs::canonicalize(reference) {
+                referenced.insert(reference);
+            }
+        }
+    }
+    files.retain(|file| fs::canonicalize(file).map_or(true, |file| !referenced.contains(&file)));
+    if files.is_empty() {
+        return Err(ConfigError::Invalid(format!(
+            "'{}' contains no config files to push",
+            path.display()
+        ))
+        .into());
+    }
+    Ok(files)
+}
+
+/// Totals for a push, independent of the order operations finished in
+#[derive(Debug, Default, Serialize)]
+pub struct PushSummary {
+    pub repositories: usize,
+    pub secrets: usize,
+    pub variables_changed: usize,
+    pub variables_unchanged: usize,
+    pub failed: usize,
+}
+
+impl PushSummary {
+    /// Add the totals of another part of the push
+    pub fn add(&mut self, other: &PushSummary) {
+        self.repositories += other.repositories;
+        self.secrets += other.secrets;
+        self.variables_changed += other.variables_changed;
+        self.variables_unchanged += other.variables_unchanged;
+        self.failed += other.failed;
+    }
+}
+
+/// Outcome of pushing one secret or variable
+#[derive(Debug, Serialize)]
+pub struct ItemReport {
+    pub name: String,
+    pub status: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Outcomes within one repository, or within the organization when
+/// `repository` is `None`
+#[derive(Debug, Serialize)]
+pub struct ScopeReport {
+    pub repository: Option<String>,
+    pub items: Vec<ItemReport>,
+}
+
+impl ScopeReport {
+    pub fn failed(&self) -> usize {
+        self.items
+            .iter()
+            .filter(|item| item.error.is_some())
+            .count()
+    }
+}
+
+/// Result of a whole push, in config order
+#[derive(Debug, Default, Serialize)]
+pub struct PushReport {
+    pub scopes: Vec<ScopeReport>,
+    pub summary: PushSummary,
+}
+
+impl PushReport {
+    /// Add the results of another push, such as that of another config file
+    pub fn merge(&mut self, other: PushReport) {
+        self.scopes.extend(other.scopes);
+        self.summary.add(&other.summary);
+    }
+
+    /// A config file that failed before any of its repositories were pushed,
+    /// such as when its repositories could not be looked up
+    pub fn file_failed(file: &Path, error: GsmError) -> PushReport {
+        PushReport {
+            scopes: vec![ScopeReport {
+                repository: None,
+                items: vec![ItemReport {
+                    name: file.display().to_string(),
+                    status: "failed".to_string(),
+                    error: Some(error.to_string()),
+                }],
+            }],
+            summary: PushSummary {
+                failed: 1,
+                ..PushSummary::default()
+            },
+        }
+    }
+
+    /// Print one row per repository with its success and failure counts
+    pub fn print_table(&self) {
+        let names: Vec<&str> = self
+            .scopes
+            .iter()
+            .map(|scope| scope.repository.as_deref().unwrap_or("(organization)"))
+            .collect();
+        let width = names
+            .iter()
+            .map(|name| name.len())
+            .chain(["Repository".len()])
+            .max()
+            .unwrap_or_default();
+
+        println!();
+        println!(
+            "{:<width$}  {:>9}  {:>6}  Error",
+            "Repository", "Succeeded", "Failed"
+        );
+        for (name, scope) in names.iter().zip(&self.scopes) {
+            let failed = scope.failed();
+            let error = scope
+                .items
+                .iter()
+                .find_map(|item| item.error.as_deref())
+                .unwrap_or("");
+            let row = format!(
+                "{:<width$}  {:>9}  {:>6}  {}",
+                name,
+                scope.items.len() - failed,
+                failed,
+                error
+            );
+            if failed > 0 {
+                println!("{}", row.red());
+            } else {
+                println!("{}", row);
+            }
+        }
+        println!();
+    }
+}
+
+/// Output of one unit of work, printed only once the unit is complete so
+/// concurrent work never interleaves on the terminal
+#[derive(Debug, Default)]
+struct Output {
+    lines: Vec<String>,
+    summary: PushSummary,
+    items: Vec<ItemReport>,
+    /// First failure, returned as-is when not continuing past errors
+    error: Option<GsmError>,
+}
+
+impl Output {
+    fn line(&mut self, line: String) {
+        self.lines.push(line);
+    }
+
+    fn secret(&mut self, indent: &str, name: String) {
+        self.summary.secrets += 1;
+        self.lines.push(format!("{}- {}: pushed", indent, name));
+        self.record(name, "pushed");
+    }
+
+    fn variable(&mut self, indent: &str, name: String, outcome: &str) {
+        if outcome == "unchanged" {
+            self.summary.variables_unchanged += 1;
+        } else {
+            self.summary.variables_changed += 1;
+        }
+        self.lines
+            .push(format!("{}- {}: {}", indent, name, outcome));
+        self.record(name, outcome);
+    }
+
+    fn record(&mut self, name: String, status: &str) {
+        self.items.push(ItemReport {
+            name,
+            status: status.to_string(),
+            error: None,
+        });
+    }
+
+    fn fail(&mut self, indent: &str, name: String, error: GsmError) {
+        self.summary.failed += 1;
+        self.lines.push(format!(
+            "{}- {}: {} ({})",
+            indent,
+            name,
+            "failed".red(),
+            error
+        ));
+        self.items.push(ItemReport {
+            name,
+            status: "failed".to_string(),
+            error: Some(error.to_string()),
+        });
+        self.error.get_or_insert(error);
+    }
+
+    fn extend(&mut self, other: Output) {
+        self.lines.extend(other.lines);
+        self.summary.add(&other.summary);
+        self.items.extend(other.items);
+        if let Some(error) = other.error {
+            self.error.get_or_insert(error);
+        }
+    }
+
+    fn print(&self) {
+        for line in &self.lines {
+            println!("{}", line);
+        }
+    }
+}
+
+/// Upsert every secret and variable declared in a config
+///
+/// Repositories and the secrets within them are pushed concurrently, bounded
+/// by the client's request limit. Output is printed per repository in config
+/// order. Unless `continue_on_error` is set, the first failure is returned
+/// once its repository has finished.
+pub async fn push_config(
+    github_client: &GithubClient,
+    config: &Config,
+    continue_on_error: bool,
+) -> Result<PushReport> {
+    let mut report = PushReport::default();
+    let mut record = |repository: Option<&str>, mut output: Output| -> Result<()> {
+        output.print();
+        if let Some(error) = output.error.take()
+            && !continue_on_error
+        {
+            return Err(error);
+        }
+        report.merge(PushReport {
+            scopes: vec![ScopeReport {
+                repository: repository.map(str::to_string),
+                items: output.items,
+            }],
+            summary: output.summary,
+        });
+        Ok(())
+    };
+
+    let mut repos = stream::iter(&config.repositories)
+        .map(|repo| async move { (repo, push_repo(github_client, config, repo).await) })
+        .buffered(github_client.concurrency());
+    while let Some((repo, output)) = repos.next().await {
+        record(Some(repo), output)?;
+    }
+
+    if !config.org_secrets.is_empty() || !config.org_vars.is_empty() {
+        let selected = config
+            .org_secrets
+            .values()
+            .flat_map(|secret| &secret.selected_repositories)
+            .chain(
+                config
+                    .org_vars
+                    .values()
+                    .flat_map(|variable| &variable.selected_repositories),
+            );
+        let mut output = Output::default();
+        match plan::resolve_repository_ids(github_client, &config.org, selected).await {
+            Ok(repo_ids) => {
+                if !config.org_secrets.is_empty() {
+                    output.extend(push_org_secrets(github_client, config, &repo_ids).await);
+                }
+                if !config.org_vars.is_empty() {
+                    output.extend(push_org_vars(github_client, config, &repo_ids).await);
+                }
+            }
+            Err(error) => {
+                output.line(format!("Pushing to org: {}...", config.org));
+                output.fail("  ", "selected repositories".to_string(), error);
+            }
+        }
+        record(None, output)?;
+    }
+    Ok(report)
+}
+
+/// Names of a map in sorted order, so output does not depend on hashing
+fn sorted_keys<V>(map: &HashMap<String, V>) -> Vec<&String> {
+    let mut keys: Vec<&String> = map.keys().collect();
+    keys.sort();
+    keys
+}
+
+/// Push the secrets, variables and environments of one repository
+async fn push_repo(github_client: &GithubClient, config: &Config, repo: &str) -> Output {
+    let org = config.org.as_str();
+    let mut output = Output::default();
+    output.summary.repositories = 1;
+    output.line(format!("Pushing secrets to repo: {}...", repo));
+
+    // Fetch the public key of every store in use once, up front
+    let repo_secrets = config.repo_secrets(repo);
+    let targets: BTreeSet<SecretTarget> = repo_secrets
+        .keys()
+        .flat_map(|name| config.secret_targets(name).iter().copied())
+        .collect();
+    let keys = try_join_all(
+        targets
+            .iter()
+            .map(|&target| github_client.get_repo_public_key(target, org, repo)),
+    )
+    .await;
+    let keys = match keys {
+        Ok(keys) => keys,
+        Err(error) => {
+            output.fail("  ", "public key".to_string(), error.into());
+            return output;
+        }
     };
-    let github_client = GithubClient::new(token, api_url);
-
-    for repo in &config.repositories {
-        println!("Pushing secrets to repo: {}...", repo);
-        let public_key = github_client.get_repo_public_key(&config.org, repo).await?;
-        for (secret_name, value) in &config.env {
-            let encrypted = encrypt_github_secret(&public_key.key, value)?;
-            github_client
-                .push_repo_secret(
-                    &config.org,
-                    repo,
-                    secret_name,
-                    &encrypted,
-                    &public_key.key_id,
+    let public_keys: HashMap<SecretTarget, _> = targets.into_iter().zip(keys).collect();
+
+    let secrets = repo_secrets.iter().flat_map(|(&name, &value)| {
+        config
+            .secret_targets(name)
+            .iter()
+            .map(move |&target| (name, value, target))
+    });
+    let pushed = join_all(secrets.map(|(name, value, target)| {
+        let public_key = &public_keys[&target];
+        async move {
+            let result = async {
+                let encrypted = encrypt_github_secret(&public_key.key, value)?;
+                github_client
+                    .push_repo_secret(target, org, repo, name, &encrypted, &public_key.key_id)
+                    .await?;
+                Ok::<_, GsmError>(())
+            };
+            (plan::secret_label(name, target), result.await)
+        }
+    }))
+    .await;
+    for (label, result) in pushed {
+        match result {
+            Ok(()) => output.secret("  ", label),
+            Err(error) => output.fail("  ", label, error),
+        }
+    }
+
+    let scope = VariableScope::Repo { org, repo };
+    let variables = join_all(
+        config
+            .repo_vars(repo)
+            .into_iter()
+            .map(|(name, value)| async move {
+                let outcome = push_variable(github_client, scope, name, value, None).await;
+                (format!("{} (variable)", name), outcome)
+            }),
+    )
+    .await;
+    for (label, outcome) in variables {
+        match outcome {
+            Ok(outcome) => output.variable("  ", label, outcome),
+            Err(error) => output.fail("  ", label, error),
+        }
+    }
+
+    let environments =
+        join_all(sorted_keys(&config.environments).into_iter().map(|name| {
+            push_environment(github_client, org, repo, name, &config.environments[name])
+        }))
+        .await;
+    for environment in environments {
+        output.extend(environment);
+    }
+    output
+}
+
+/// Push the secrets and variables of one deployment environment, creating it if missing
+async fn push_environment(
+    github_client: &GithubClient,
+    org: &str,
+    repo: &str,
+    environment_name: &str,
+    environment: &Environment,
+) -> Output {
+    let mut output = Output::default();
+    output.line(format!("  Environment: {}", environment_name));
+    let label = format!("environment {}", environment_name);
+    match github_client
+        .environment_exists(org, repo, environment_name)
+        .await
+    {
+        Ok(true) => {}
+        Ok(false) => {
+            if let Err(error) = github_client
+                .create_environment(org, repo, environment_name)
+                .await
+            {
+                output.fail("    ", label, error.into());
+                return output;
+            }
+            output.line("    (created environment)".to_string());
+        }
+        Err(error) => {
+            output.fail("    ", label, error.into());
+            return output;
+        }
+    }
+
+    if !environment.env.is_empty() {
+        match github_client
+            .get_environment_public_key(org, repo, environment_name)
+            .await
+        {
+            Ok(public_key) => {
+                let public_key = &public_key;
+                let pushed = join_all(sorted_keys(&environment.env).into_iter().map(
+                    |name| async move {
+                        let result = async {
+                            let encrypted =
+                                encrypt_github_secret(&public_key.key, &environment.env[name])?;
+                            github_client
+                                .push_environment_secret(
+                                    org,
+                                    repo,
+                                    environment_name,
+                                    name,
+                                    &encrypted,
+                                    &public_key.key_id,
+                                )
+                                .await?;
+                            Ok::<_, GsmError>(())
+                        };
+                        (
+                            format!("{} (environment {})", name, environment_name),
+                            result.await,
+                        )
+                    },
+                ))
+                .await;
+                for (label, result) in pushed {
+                    match result {
+                        Ok(()) => output.secret("    ", label),
+                        Err(error) => output.fail("    ", label, error),
+                    }
+                }
+            }
+            Err(error) => output.fail("    ", format!("{} public key", label), error.into()),
+        }
+    }
+
+    let scope = VariableScope::Environment {
+        org,
+        repo,
+        environment: environment_name,
+    };
+    let variables = join_all(
+        sorted_keys(&environment.vars)
+            .into_iter()
+            .map(|name| async move {
+                let outcome =
+                    push_variable(github_client, scope, name, &environment.vars[name], None).await;
+                (
+                    format!("{} (variable, environment {})", name, environment_name),
+                    outcome,
                 )
-                .await?;
-            println!("  - {}: pushed", secret_name);
+            }),
+    )
+    .await;
+    for (label, outcome) in variables {
+        match outcome {
+            Ok(outcome) => output.variable("    ", label, outcome),
+            Err(error) => output.fail("    ", label, error),
         }
     }
-    println!("All secrets pushed successfully!");
-    Ok(())
+    output
+}
+
+/// Push organization-level secrets to every store they target
+async fn push_org_secrets(
+    github_client: &GithubClient,
+    config: &Config,
+    repo_ids: &HashMap<String, u64>,
+) -> Output {
+    let org = config.org.as_str();
+    let mut output = Output::default();
+    output.line(format!("Pushing secrets to org: {}...", org));
+
+    let names = sorted_keys(&config.org_secrets);
+    let targets: BTreeSet<SecretTarget> = names
+        .iter()
+        .flat_map(|name| config.org_secrets[*name].targets.iter().copied())
+        .collect();
+    let keys = try_join_all(
+        targets
+            .iter()
+            .map(|&target| github_client.get_org_public_key(target, org)),
+    )
+    .await;
+    let keys = match keys {
+        Ok(keys) => keys,
+        Err(error) => {
+            output.fail("  ", "org public key".to_string(), error.into());
+            return output;
+        }
+    };
+    let public_keys: HashMap<SecretTarget, _> = targets.into_iter().zip(keys).collect();
+
+    let secrets = names.iter().flat_map(|&name| {
+        config.org_secrets[name]
+            .targets
+            .iter()
+            .map(move |&target| (name, target))
+    });
+    let pushed = join_all(secrets.map(|(name, target)| {
+        let secret = &config.org_secrets[name];
+        let public_key = &public_keys[&target];
+        async move {
+            let result = async {
+                let selected_ids =
+                    plan::selected_ids(secret.visibility, &secret.selected_repositories, repo_ids);
+                let encrypted = encrypt_github_secret(&public_key.key, &secret.value)?;
+                let body = OrgSecretBody {
+                    encrypted_value: &encrypted,
+                    key_id: &public_key.key_id,
+                    visibility: secret.visibility,
+                    selected_repository_ids: (secret.visibility == Visibility::Selected)
+                        .then_some(selected_ids.as_slice()),
+                };
+                github_client
+                    .push_org_secret(target, org, name, &body)
+                    .await?;
+                Ok::<_, GsmError>(())
+            };
+            (
+                plan::secret_label(name, target),
+                secret.visibility,
+                result.await,
+            )
+        }
+    }))
+    .await;
+    for (label, visibility, result) in pushed {
+        match result {
+            Ok(()) => output.secret("  ", format!("{} ({})", label, visibility)),
+            Err(error) => output.fail("  ", label, error),
+        }
+    }
+    output
+}
+
+/// Push organization-level variables
+async fn push_org_vars(
+    github_client: &GithubClient,
+    config: &Config,
+    repo_ids: &HashMap<String, u64>,
+) -> Output {
+    let mut output = Output::default();
+    output.line(format!("Pushing variables to org: {}...", config.org));
+    let scope = VariableScope::Org { org: &config.org };
+
+    let variables = join_all(sorted_keys(&config.org_vars).into_iter().map(|name| {
+        let variable = &config.org_vars[name];
+        async move {
+            let ids = plan::selected_ids(
+                variable.visibility,
+                &variable.selected_repositories,
+                repo_ids,
+            );
+            let outcome = push_variable(
+                github_client,
+                scope,
+                name,
+                &variable.value,
+                Some((variable.visibility, &ids)),
+            )
+            .await;
+            (name, variable.visibility, outcome)
+        }
+    }))
+    .await;
+    for (name, visibility, outcome) in variables {
+        match outcome {
+            Ok(outcome) => output.variable(
+                "  ",
+                format!("{} (variable, {})", name, visibility),
+                outcome,
+            ),
+            Err(error) => output.fail("  ", format!("{} (variable)", name), error),
+        }
+    }
+    output
+}
+
+/// Create or update a variable, leaving it alone when it already matches
+async fn push_variable(
+    github_client: &GithubClient,
+    scope: VariableScope<'_>,
+    name: &str,
+    value: &str,
+    org_access: Option<(Visibility, &[u64])>,
+) -> Result<&'static str> {
+    let body = VariableBody {
+        name,
+        value,
+        visibility: org_access.map(|(visibility, _)| visibility),
+        selected_repository_ids: org_access
+            .filter(|(visibility, _)| *visibility == Visibility::Selected)
+            .map(|(_, ids)| ids),
+    };
+
+    let Some(existing) = github_client.get_variable(scope, name).await? else {
+        github_client.create_variable(scope, &body).await?;
+        return Ok("created");
+    };
+
+    let mut unchanged = existing.value == value && existing.visibility == body.visibility;
+    if unchanged
+        && let (VariableScope::Org { org }, Some(wanted)) = (scope, body.selected_repository_ids)
+    {
+        let mut current = github_client
+            .get_org_variable_repository_ids(org, name)
+            .await?;
+        let mut wanted = wanted.to_vec();
+        current.sort_unstable();
+        wanted.sort_unstable();
+        unchanged = current == wanted;
+    }
+
+    if unchanged {
+        Ok("unchanged")
+    } else {
+        github_client.update_variable(scope, &body).await?;
+        Ok("updated")
+    }
 }
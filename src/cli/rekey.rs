@@ -0,0 +1,148 @@
+use crate::cli::crypto_ops::{self, Credentials};
+use crate::cli::key_provider::{KeyArgs, KeyProvider, KeySource, Prompt};
+use crate::cli::{keys, utils};
+use crate::config::ConfigError;
+use crate::error::Result;
+use clap::Parser;
+use serde_yaml::Value;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Re-encrypt config files under a new encryption key
+///
+/// Every file is re-encrypted in memory and checked against the new key
+/// before any is written, then replaced atomically and read back, so no
+/// plaintext ever touches the disk.
+#[derive(Parser, Debug)]
+pub struct RekeyArgs {
+    /// Encrypted config files, or directories to search for them
+    #[arg(required = true)]
+    pub paths: Vec<PathBuf>,
+    /// Where to read the current key from, like `--key-source` elsewhere
+    #[arg(long, value_name = "SOURCE")]
+    pub old_key_source: Option<KeySource>,
+    /// Where to read the new key from; `prompt` asks for it twice
+    #[arg(long, value_name = "SOURCE", default_value = "env:NEW_ENCRYPTION_KEY")]
+    pub new_key_source: KeySource,
+}
+
+pub fn run(args: &RekeyArgs) -> Result<()> {
+    let old_source = KeyArgs {
+        key_source: args.old_key_source.clone(),
+        ..KeyArgs::default()
+    }
+    .source(&args.paths[0])?;
+    let old_key = old_source.provider()?.key()?;
+    let new_key = match &args.new_key_source {
+        KeySource::Prompt => Prompt {
+            label: "New encryption key".to_string(),
+            confirm: true,
+        }
+        .key()?,
+        source => source.provider()?.key()?,
+    };
+    if old_key == new_key {
+        return Err(ConfigError::Invalid(format!(
+            "{} and {} hold the same key",
+            old_source, args.new_key_source
+        ))
+        .into());
+    }
+    let mut old = Credentials {
+        password: Some(old_key),
+        ..Credentials::default()
+    };
+    let mut new = Credentials {
+        password: Some(new_key),
+        ..Credentials::default()
+    };
+
+    let mut rekeyed = Vec::new();
+    for (path, mut document) in encrypted_files(&args.paths)? {
+        let changed = crypto_ops::rekey_document(&mut document, &mut old, &mut new)
+            .map_err(|e| crypto_ops::in_file(&path, e))?;
+        if changed {
+            rekeyed.push((path, serde_yaml::to_string(&document)?));
+        } else {
+            println!(
+                "Skipped '{}': its key is not protected by a password",
+                path.display()
+            );
+        }
+    }
+
+    // Nothing is written until every file re-encrypted cleanly
+    for (path, content) in &rekeyed {
+        utils::write_atomic(path, content.as_bytes())?;
+        let mut written: Value = serde_yaml::from_str(&fs::read_to_string(path)?)?;
+        crypto_ops::decrypt_document(&mut written, &mut new)?;
+        println!("Rekeyed '{}' ✅", path.display());
+    }
+    println!(
+        "Rekeyed {} file(s); the key from {} now decrypts them",
+        rekeyed.len(),
+        args.new_key_source
+    );
+    Ok(())
+}
+
+/// The encrypted config files named by `paths`, with directories searched
+/// recursively for encrypted `.yaml` and `.yml` files
+fn encrypted_files(paths: &[PathBuf]) -> Result<Vec<(PathBuf, Value)>> {
+    let mut files = Vec::new();
+    for path in paths {
+        if path.is_dir() {
+            let found = files.len();
+            collect_directory(path, &mut files)?;
+            if files.len() == found {
+                return Err(ConfigError::Invalid(format!(
+                    "'{}' contains no encrypted config files",
+                    path.display()
+                ))
+                .into());
+            }
+        } else {
+            let mut document: Value = serde_yaml::from_str(&fs::read_to_string(path)?)?;
+            if !crypto_ops::is_encrypted(&mut document) {
+                return Err(
+                    ConfigError::Invalid(format!("'{}' is not encrypted", path.display())).into(),
+                );
+            }
+            keys::ensure_encrypted(&mut document, path)?;
+            files.push((path.clone(), document));
+        }
+    }
+    Ok(files)
+}
+
+fn collect_directory(dir: &Path, files: &mut Vec<(PathBuf, Value)>) -> Result<()> {
+    let mut entries = fs::read_dir(dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<std::io::Result<Vec<_>>>()?;
+    entries.sort();
+    for path in entries {
+        let hidden = path
+            .file_name()
+            .is_some_and(|name| name.to_string_lossy().starts_with('.'));
+        if hidden {
+            continue;
+        }
+        if path.is_dir() {
+            collect_directory(&path, files)?;
+            continue;
+        }
+        let is_yaml = path
+            .extension()
+            .is_some_and(|extension| extension == "yaml" || extension == "yml");
+        if !is_yaml {
+            continue;
+        }
+        let mut document: Value = serde_yaml::from_str(&fs::read_to_string(&path)?)?;
+        // Raw configs and other YAML files in the tree are left alone
+        if crypto_ops::is_encrypted(&mut document) {
+            keys::ensure_encrypted(&mut document, &path)?;
+            files.push((path, document));
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,66 @@
+use crate::config::{self, Config};
+use crate::error::Result;
+use crate::github::{GithubClient, OrgRepository};
+use futures::future::try_join_all;
+use std::collections::BTreeSet;
+
+/// Expand `select_repositories` into the final `repositories` list
+///
+/// Explicitly listed repositories keep their order and come first, followed
+/// by selected ones sorted by name. Exclusions apply to both.
+pub async fn resolve(github_client: &GithubClient, config: &mut Config) -> Result<()> {
+    let selected = select(github_client, config).await?;
+    merge(config, selected)
+}
+
+/// Names of the repositories matching the selection rules, looked up on GitHub
+async fn select(github_client: &GithubClient, config: &Config) -> Result<BTreeSet<String>> {
+    let selection = &config.select_repositories;
+    let names = config::compile_patterns(&selection.names)?;
+
+    let mut selected = BTreeSet::new();
+    if !selection.names.is_empty() || !selection.topics.is_empty() {
+        for repo in github_client.list_org_repositories(&config.org).await? {
+            let by_topic = repo.topics.iter().any(|topic| {
+                selection
+                    .topics
+                    .iter()
+                    .any(|wanted| wanted.eq_ignore_ascii_case(topic))
+            });
+            if !repo.archived && (by_topic || config::matches_any(&names, &repo.name)) {
+                selected.insert(repo.name);
+            }
+        }
+    }
+    let teams = try_join_all(
+        selection
+            .teams
+            .iter()
+            .map(|team| github_client.list_team_repositories(&config.org, team)),
+    )
+    .await?;
+    selected.extend(
+        teams
+            .into_iter()
+            .flatten()
+            .filter(|repo| !repo.archived)
+            .map(|repo: OrgRepository| repo.name),
+    );
+    Ok(selected)
+}
+
+/// Combine the explicit list with selected repositories and drop exclusions
+pub fn merge(config: &mut Config, selected: BTreeSet<String>) -> Result<()> {
+    let exclude = config::compile_patterns(&config.select_repositories.exclude)?;
+    let mut repositories: Vec<String> = Vec::new();
+    for repo in config.repositories.iter().cloned().chain(selected) {
+        let duplicate = repositories
+            .iter()
+            .any(|existing| existing.eq_ignore_ascii_case(&repo));
+        if !duplicate && !config::matches_any(&exclude, &repo) {
+            repositories.push(repo);
+        }
+    }
+    config.repositories = repositories;
+    Ok(())
+}
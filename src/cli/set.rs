@@ -0,0 +1,65 @@
+use crate::cli::crypto_ops::{self, FileCipher};
+use crate::cli::key_provider::KeyArgs;
+use crate::cli::keys::{self, SecretKey};
+use crate::crypto::Kdf;
+use crate::error::Result;
+use clap::Parser;
+use serde_yaml::Value;
+use std::fs;
+use std::io::{self, IsTerminal, Read};
+use std::path::PathBuf;
+
+/// Encrypt and store one secret, leaving every other value untouched
+///
+/// The value is read from stdin, or from `--value-file`, so it never appears
+/// in shell history.
+#[derive(Parser, Debug)]
+pub struct SetArgs {
+    /// Path to the encrypted config file
+    #[arg(short, long)]
+    pub file: PathBuf,
+    /// Secret key, such as `API_KEY` or `environments.production.env.DEPLOY_TOKEN`
+    pub key: String,
+    /// Read the value from this file instead of stdin
+    #[arg(long, value_name = "PATH")]
+    pub value_file: Option<PathBuf>,
+    #[command(flatten)]
+    pub encryption: KeyArgs,
+}
+
+pub fn run(args: &SetArgs) -> Result<()> {
+    let key = SecretKey::parse(&args.key)?;
+    let mut document: Value = serde_yaml::from_str(&fs::read_to_string(&args.file)?)?;
+    keys::ensure_encrypted(&mut document, &args.file)?;
+
+    let value = match &args.value_file {
+        Some(path) => fs::read_to_string(path)?,
+        None => {
+            if io::stdin().is_terminal() {
+                eprintln!("Enter the value for '{}', then press Ctrl-D:", key);
+            }
+            let mut value = String::new();
+            io::stdin().read_to_string(&mut value)?;
+            value
+        }
+    };
+    // Drop the newline that terminals and most editors add at the end
+    let value = value
+        .strip_suffix('\n')
+        .map(|value| value.strip_suffix('\r').unwrap_or(value))
+        .unwrap_or(&value);
+
+    let protection = crypto_ops::protection_for(&args.file, &document, &Kdf::default())?;
+    // Without metadata the file gets its first key from this one
+    let mut credentials = if document.get(crypto_ops::METADATA_KEY).is_none() {
+        args.encryption.new_key_credentials(&args.file)?
+    } else {
+        args.encryption.credentials(&args.file)?
+    };
+    let cipher = FileCipher::init(&mut document, &mut credentials, &protection)?;
+    key.set(&mut document, cipher.encrypt(&key.path(), value)?)?;
+    cipher.seal(&mut document)?;
+    fs::write(&args.file, serde_yaml::to_string(&document)?)?;
+    println!("Set '{}' in '{}' ✅", key, args.file.display());
+    Ok(())
+}
@@ -0,0 +1,143 @@
+use crate::cli::github_args::GithubArgs;
+use crate::cli::key_provider::KeyArgs;
+use crate::cli::{crypto_ops, push, repositories};
+use crate::config::{self, Config, SecretTarget};
+use crate::error::Result;
+use crate::github::{GithubClient, SecretInfo};
+use crate::plan;
+use clap::Parser;
+use glob::Pattern;
+use std::collections::HashSet;
+use std::path::PathBuf;
+
+/// Push secrets and remove those no longer declared in the config
+///
+/// Only repository and environment secrets are considered for pruning;
+/// organization secrets are often shared with other tooling and are left alone.
+#[derive(Parser, Debug)]
+pub struct SyncArgs {
+    /// Path to a raw or encrypted config file
+    #[arg(short, long)]
+    pub file: PathBuf,
+    /// Delete remote secrets that are not declared in the config
+    #[arg(long)]
+    pub prune: bool,
+    /// Secret name pattern that must never be deleted (repeatable)
+    #[arg(long = "protect", value_name = "PATTERN")]
+    pub protect: Vec<String>,
+    #[command(flatten)]
+    pub github: GithubArgs,
+    #[command(flatten)]
+    pub encryption: KeyArgs,
+}
+
+pub async fn run(args: &SyncArgs) -> Result<()> {
+    let mut credentials = args.encryption.credentials(&args.file)?;
+    let mut config = crypto_ops::load_config(&args.file, &mut credentials)?;
+    let mut patterns = config.protected.clone();
+    patterns.extend(args.protect.iter().cloned());
+    let protected = config::compile_patterns(&patterns)?;
+    let github_client = args.github.client(&config.org)?;
+    repositories::resolve(&github_client, &mut config).await?;
+
+    push::push_config(&github_client, &config, false).await?;
+
+    let undeclared = undeclared_secrets(&github_client, &config, &protected, args.prune).await?;
+    if undeclared > 0 {
+        println!(
+            "Found {} undeclared secret(s); re-run with --prune to delete them",
+            undeclared
+        );
+    } else {
+        println!("All secrets are in sync!");
+    }
+    Ok(())
+}
+
+/// Find the repository and environment secrets that are neither declared nor
+/// protected, deleting them when `prune` is set; returns how many were only
+/// reported
+pub async fn undeclared_secrets(
+    github_client: &GithubClient,
+    config: &Config,
+    protected: &[Pattern],
+    prune: bool,
+) -> Result<usize> {
+    let mut undeclared = 0;
+    for repo in &config.repositories {
+        println!("Checking for undeclared secrets in repo: {}...", repo);
+        let repo_secrets = config.repo_secrets(repo);
+        for target in config.managed_targets() {
+            let declared = repo_secrets
+                .keys()
+                .copied()
+                .filter(|name| config.secret_targets(name).contains(&target));
+            let remote = github_client
+                .list_repo_secrets(target, &config.org, repo)
+                .await?;
+            for name in extra_secrets(remote, declared, protected, target) {
+                let label = plan::secret_label(&name, target);
+                if prune {
+                    github_client
+                        .delete_repo_secret(target, &config.org, repo, &name)
+                        .await?;
+                    println!("  - {}: deleted", label);
+                } else {
+                    println!("  - {}: not in config", label);
+                    undeclared += 1;
+                }
+            }
+        }
+        for (environment_name, environment) in &config.environments {
+            let remote = github_client
+                .list_environment_secrets(&config.org, repo, environment_name)
+                .await?;
+            let extra = extra_secrets(
+                remote,
+                environment.env.keys().map(String::as_str),
+                protected,
+                SecretTarget::Actions,
+            );
+            for name in extra {
+                if prune {
+                    github_client
+                        .delete_environment_secret(&config.org, repo, environment_name, &name)
+                        .await?;
+                    println!("  - {} (environment {}): deleted", name, environment_name);
+                } else {
+                    println!(
+                        "  - {} (environment {}): not in config",
+                        name, environment_name
+                    );
+                    undeclared += 1;
+                }
+            }
+        }
+    }
+    Ok(undeclared)
+}
+
+/// Remote secrets that are neither declared nor protected
+fn extra_secrets<'a>(
+    remote: Vec<SecretInfo>,
+    declared: impl Iterator<Item = &'a str>,
+    protected: &[Pattern],
+    target: SecretTarget,
+) -> Vec<String> {
+    let declared: HashSet<String> = declared.map(|name| name.to_uppercase()).collect();
+    let mut extra = Vec::new();
+    for secret in remote {
+        if declared.contains(&secret.name.to_uppercase()) {
+            continue;
+        }
+        if config::is_protected(protected, &secret.name) {
+            println!(
+                "  - {}: protected, kept",
+                plan::secret_label(&secret.name, target)
+            );
+            continue;
+        }
+        extra.push(secret.name);
+    }
+    extra
+}
@@ -0,0 +1,48 @@
+use crate::cli::crypto_ops::{self, FileCipher};
+use crate::cli::key_provider::KeyArgs;
+use crate::cli::keys::SecretKey;
+use crate::config::ConfigError;
+use crate::error::Result;
+use clap::Parser;
+use serde_yaml::Value;
+use std::fs;
+use std::path::PathBuf;
+
+/// Remove one secret from a config file
+#[derive(Parser, Debug)]
+pub struct UnsetArgs {
+    /// Path to the encrypted config file
+    #[arg(short, long)]
+    pub file: PathBuf,
+    /// Secret key, such as `API_KEY` or `org_secrets.SHARED_TOKEN`
+    pub key: String,
+    #[command(flatten)]
+    pub encryption: KeyArgs,
+}
+
+pub fn run(args: &UnsetArgs) -> Result<()> {
+    let key = SecretKey::parse(&args.key)?;
+    let mut document: Value = serde_yaml::from_str(&fs::read_to_string(&args.file)?)?;
+    // Encrypted files carry a MAC over all their entries, which needs the key
+    let cipher = match document.get(crypto_ops::METADATA_KEY) {
+        Some(_) => Some(FileCipher::open(
+            &document,
+            &mut args.encryption.credentials(&args.file)?,
+        )?),
+        None => None,
+    };
+    if !key.unset(&mut document)? {
+        return Err(ConfigError::Invalid(format!(
+            "'{}' is not set in '{}'",
+            key,
+            args.file.display()
+        ))
+        .into());
+    }
+    if let Some(cipher) = cipher {
+        cipher.seal(&mut document)?;
+    }
+    fs::write(&args.file, serde_yaml::to_string(&document)?)?;
+    println!("Removed '{}' from '{}' ✅", key, args.file.display());
+    Ok(())
+}
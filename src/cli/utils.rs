@@ -1,5 +1,8 @@
+use crate::config::ConfigError;
+use crate::crypto::CryptoError;
 use crate::error::Result;
 use std::fs;
+use std::io::Write;
 use std::path::{Path, PathBuf};
 
 /// Generate output path based on input path and suffix
@@ -15,10 +18,10 @@ pub fn process_directory<F>(
     parent_dir: &Path,
     input_subdir: &str,
     output_subdir: &str,
-    processor: F,
+    mut processor: F,
 ) -> Result<()>
 where
-    F: Fn(&Path, &Path) -> Result<()>,
+    F: FnMut(&Path, &Path) -> Result<()>,
 {
     let input_dir = parent_dir.join(input_subdir);
     let output_dir = parent_dir.join(output_subdir);
@@ -38,3 +41,50 @@ where
 
     Ok(())
 }
+
+/// Replace a file in a single rename, so it holds either the old or the new
+/// content even if gsm is interrupted
+pub fn write_atomic(path: &Path, contents: &[u8]) -> Result<()> {
+    let dir = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let mut temp = tempfile::NamedTempFile::new_in(dir)?;
+    temp.write_all(contents)?;
+    temp.as_file().sync_all()?;
+    if let Ok(metadata) = fs::metadata(path) {
+        temp.as_file().set_permissions(metadata.permissions())?;
+    }
+    temp.persist(path).map_err(|e| e.error)?;
+    Ok(())
+}
+
+/// Read a key or identity file, refusing files that other users can access
+pub fn read_private(path: &Path) -> Result<String> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = fs::metadata(path)?.permissions().mode();
+        if mode & 0o077 != 0 {
+            return Err(CryptoError::KeyError(format!(
+                "'{}' is accessible by other users (mode {:o}); run `chmod 600` on it",
+                path.display(),
+                mode & 0o777
+            ))
+            .into());
+        }
+    }
+    Ok(fs::read_to_string(path)?)
+}
+
+/// The user's home directory from `HOME`
+///
+/// Without one there is nowhere to look for the user's own files, and
+/// falling back to a relative path would pick them up from the current
+/// directory instead.
+pub fn home_dir() -> Result<PathBuf> {
+    match std::env::var_os("HOME") {
+        Some(home) if !home.is_empty() => Ok(PathBuf::from(home)),
+        _ => Err(ConfigError::Invalid("no home directory: HOME is not set".to_string()).into()),
+    }
+}
@@ -1,6 +1,11 @@
-use crate::config;
+use crate::cli::github_args::GithubArgs;
+use crate::cli::key_provider::KeyArgs;
+use crate::cli::{crypto_ops, repositories};
+use crate::config::Config;
 use crate::error::Result;
 use clap::Parser;
+use std::collections::BTreeSet;
+use std::path::Path;
 
 /// Validate a configuration file
 #[derive(Parser, Debug)]
@@ -8,17 +13,100 @@ pub struct ValidateArgs {
     /// Path to the config file
     #[arg(short, long, default_value = "examples/production.yaml")]
     pub file: String,
+    /// Do not contact GitHub to resolve `select_repositories`
+    #[arg(long)]
+    pub offline: bool,
+    #[command(flatten)]
+    pub github: GithubArgs,
+    #[command(flatten)]
+    pub encryption: KeyArgs,
 }
 
-pub fn run(args: &ValidateArgs) -> Result<()> {
-    match config::load_config_from_file(&args.file) {
-        Ok(_) => {
-            println!("Config file '{}' is valid ✅", args.file);
-            Ok(())
-        }
+pub async fn run(args: &ValidateArgs) -> Result<()> {
+    let path = Path::new(&args.file);
+    let loaded = args
+        .encryption
+        .credentials(path)
+        .and_then(|mut credentials| crypto_ops::load_config(path, &mut credentials));
+    let mut config = match loaded {
+        Ok(config) => config,
         Err(e) => {
             eprintln!("Config file '{}' is invalid: {}", args.file, e);
-            Err(e.into())
+            return Err(e);
+        }
+    };
+    println!("Config file '{}' is valid ✅", args.file);
+
+    let selection = &config.select_repositories;
+    if args.offline && selection.selects() {
+        println!("Repositories listed explicitly:");
+        for repo in &config.repositories {
+            print_repository(&config, repo);
+        }
+        println!("Repository selection not resolved (--offline):");
+        for (rule, values) in [
+            ("names", &selection.names),
+            ("topics", &selection.topics),
+            ("teams", &selection.teams),
+            ("exclude", &selection.exclude),
+        ] {
+            if !values.is_empty() {
+                println!("  {}: {}", rule, values.join(", "));
+            }
         }
+        return Ok(());
+    }
+
+    if selection.selects() {
+        let github_client = args.github.client(&config.org)?;
+        repositories::resolve(&github_client, &mut config).await?;
+    } else {
+        // Exclusions alone need no lookups, so there is nothing to fetch
+        repositories::merge(&mut config, BTreeSet::new())?;
+    }
+    println!("Repositories ({}):", config.repositories.len());
+    for repo in &config.repositories {
+        print_repository(&config, repo);
+    }
+    Ok(())
+}
+
+/// Print a repository with the names of the secrets and variables it receives
+fn print_repository(config: &Config, repo: &str) {
+    let repo_override = config.repo_override(repo);
+    let label = |name: &str, overridden: bool| {
+        if overridden {
+            format!("{} (override)", name)
+        } else {
+            name.to_string()
+        }
+    };
+    let secrets: Vec<String> = config
+        .repo_secrets(repo)
+        .into_keys()
+        .map(|name| {
+            label(
+                name,
+                repo_override.is_some_and(|o| o.env.contains_key(name)),
+            )
+        })
+        .collect();
+    let vars: Vec<String> = config
+        .repo_vars(repo)
+        .into_keys()
+        .map(|name| {
+            label(
+                name,
+                repo_override.is_some_and(|o| o.vars.contains_key(name)),
+            )
+        })
+        .collect();
+
+    println!("  - {}", repo);
+    if !secrets.is_empty() {
+        println!("      secrets: {}", secrets.join(", "));
+    }
+    if !vars.is_empty() {
+        println!("      variables: {}", vars.join(", "));
     }
 }
@@ -0,0 +1,244 @@
+// Config layering: `extends` and `include` resolution with provenance
+
+use super::ConfigError;
+use serde_yaml::Value;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// A config document whose entries remember the file that defined them
+enum Node {
+    Map(Vec<(Value, Node)>, PathBuf),
+    Leaf(Value, PathBuf),
+}
+
+impl Node {
+    fn new(value: Value, origin: &Path) -> Node {
+        match value {
+            Value::Mapping(mapping) => Node::Map(
+                mapping
+                    .into_iter()
+                    .map(|(key, value)| (key, Node::new(value, origin)))
+                    .collect(),
+                origin.to_path_buf(),
+            ),
+            value => Node::Leaf(value, origin.to_path_buf()),
+        }
+    }
+
+    fn origin(&self) -> &Path {
+        match self {
+            Node::Map(_, origin) | Node::Leaf(_, origin) => origin,
+        }
+    }
+
+    fn into_value(self) -> Value {
+        match self {
+            Node::Map(entries, _) => Value::Mapping(
+                entries
+                    .into_iter()
+                    .map(|(key, node)| (key, node.into_value()))
+                    .collect(),
+            ),
+            Node::Leaf(value, _) => value,
+        }
+    }
+
+    /// Merge `other` into `self`, recursing into mappings
+    ///
+    /// With `strict`, a value both sides define differently is an error;
+    /// otherwise `other` wins.
+    fn merge(
+        &mut self,
+        other: Node,
+        strict: bool,
+        key: &mut Vec<String>,
+    ) -> Result<(), ConfigError> {
+        match (self, other) {
+            (Node::Map(entries, _), Node::Map(other_entries, _)) => {
+                for (name, node) in other_entries {
+                    match entries.iter_mut().find(|(existing, _)| *existing == name) {
+                        Some((_, existing)) => {
+                            key.push(key_name(&name));
+                            existing.merge(node, strict, key)?;
+                            key.pop();
+                        }
+                        None => entries.push((name, node)),
+                    }
+                }
+                Ok(())
+            }
+            (Node::Leaf(value, _), Node::Leaf(other_value, _))
+                if strict && *value == other_value =>
+            {
+                Ok(())
+            }
+            (this, other) => {
+                if strict {
+                    return Err(ConfigError::Conflict {
+                        key: key.join("."),
+                        first: this.origin().to_path_buf(),
+                        second: other.origin().to_path_buf(),
+                    });
+                }
+                *this = other;
+                Ok(())
+            }
+        }
+    }
+}
+
+fn key_name(key: &Value) -> String {
+    match key {
+        Value::String(s) => s.clone(),
+        other => serde_yaml::to_string(other)
+            .unwrap_or_default()
+            .trim()
+            .to_string(),
+    }
+}
+
+/// Load a config document with its `extends` base and `include` fragments merged in
+///
+/// The file's own values override those of its base and includes. Includes
+/// are peers, so two of them defining the same value differently is a
+/// conflict. `transform` sees each file's own document, including its
+/// references, before merging, which lets encrypted layers be verified and
+/// decrypted one file at a time.
+pub fn load_document<E: From<ConfigError>>(
+    path: &Path,
+    transform: &mut dyn FnMut(&Path, &mut Value) -> Result<(), E>,
+) -> Result<Value, E> {
+    let mut stack = Vec::new();
+    Ok(resolve(path, transform, &mut stack)?.into_value())
+}
+
+/// Files that a config file directly extends or includes
+pub fn references(path: &Path) -> Result<Vec<PathBuf>, ConfigError> {
+    let (_, extends, include) = read_layer(path, &mut |_, _| Ok::<_, ConfigError>(()))?;
+    Ok(reference_paths(path, extends, include))
+}
+
+/// Files that `document`, read from `path`, directly extends or includes
+pub fn document_references(path: &Path, document: &Value) -> Result<Vec<PathBuf>, ConfigError> {
+    let mut document = document.clone();
+    let (extends, include) = take_references(path, &mut document)?;
+    Ok(reference_paths(path, extends, include))
+}
+
+fn reference_paths(path: &Path, extends: Option<String>, include: Vec<String>) -> Vec<PathBuf> {
+    let dir = path.parent().unwrap_or_else(|| Path::new("."));
+    extends
+        .into_iter()
+        .chain(include)
+        .map(|file| dir.join(file))
+        .collect()
+}
+
+/// Read and transform one file without resolving its references, returning
+/// the document and the `extends` and `include` entries it declares
+fn read_layer<E: From<ConfigError>>(
+    path: &Path,
+    transform: &mut dyn FnMut(&Path, &mut Value) -> Result<(), E>,
+) -> Result<(Value, Option<String>, Vec<String>), E> {
+    let content = fs::read_to_string(path).map_err(ConfigError::from)?;
+    let mut document: Value = serde_yaml::from_str(&content).map_err(ConfigError::from)?;
+    transform(path, &mut document)?;
+    let (extends, include) = take_references(path, &mut document)?;
+    Ok((document, extends, include))
+}
+
+/// Remove and return the `extends` and `include` entries of a document
+fn take_references(
+    path: &Path,
+    document: &mut Value,
+) -> Result<(Option<String>, Vec<String>), ConfigError> {
+    let Some(mapping) = document.as_mapping_mut() else {
+        return Err(ConfigError::Invalid(format!(
+            "'{}' is not a YAML mapping",
+            path.display()
+        )));
+    };
+
+    let extends = match mapping.remove("extends") {
+        None => None,
+        Some(Value::String(base)) => Some(base),
+        Some(_) => {
+            return Err(ConfigError::Invalid(format!(
+                "'extends' in '{}' must be a file path",
+                path.display()
+            )));
+        }
+    };
+    let include = match mapping.remove("include") {
+        None => Vec::new(),
+        Some(Value::String(file)) => vec![file],
+        Some(value) => serde_yaml::from_value(value).map_err(|_| {
+            ConfigError::Invalid(format!(
+                "'include' in '{}' must be a file path or a list of them",
+                path.display()
+            ))
+        })?,
+    };
+    Ok((extends, include))
+}
+
+fn resolve<E: From<ConfigError>>(
+    path: &Path,
+    transform: &mut dyn FnMut(&Path, &mut Value) -> Result<(), E>,
+    stack: &mut Vec<(PathBuf, PathBuf)>,
+) -> Result<Node, E> {
+    let canonical = fs::canonicalize(path).map_err(ConfigError::from)?;
+    if let Some(start) = stack.iter().position(|(seen, _)| *seen == canonical) {
+        let chain: Vec<String> = stack[start..]
+            .iter()
+            .map(|(_, shown)| shown.display().to_string())
+            .chain([path.display().to_string()])
+            .collect();
+        return Err(ConfigError::Cycle(chain.join(" -> ")).into());
+    }
+
+    let (document, extends, include) = read_layer(path, transform)?;
+    stack.push((canonical, path.to_path_buf()));
+
+    let dir = path.parent().unwrap_or_else(|| Path::new("."));
+    let mut resolved: Option<Node> = None;
+    if let Some(base) = extends {
+        resolved = Some(resolve_reference(&dir.join(base), path, transform, stack)?);
+    }
+
+    let mut included: Option<Node> = None;
+    for file in include {
+        let node = resolve_reference(&dir.join(file), path, transform, stack)?;
+        match &mut included {
+            Some(included) => included.merge(node, true, &mut Vec::new())?,
+            None => included = Some(node),
+        }
+    }
+
+    for layer in included.into_iter().chain([Node::new(document, path)]) {
+        match &mut resolved {
+            Some(resolved) => resolved.merge(layer, false, &mut Vec::new())?,
+            None => resolved = Some(layer),
+        }
+    }
+    stack.pop();
+    Ok(resolved.expect("a file always contributes its own layer"))
+}
+
+/// Resolve a file referenced by another, naming the referencing file when it cannot be read
+fn resolve_reference<E: From<ConfigError>>(
+    path: &Path,
+    from: &Path,
+    transform: &mut dyn FnMut(&Path, &mut Value) -> Result<(), E>,
+    stack: &mut Vec<(PathBuf, PathBuf)>,
+) -> Result<Node, E> {
+    if !path.exists() {
+        return Err(ConfigError::Invalid(format!(
+            "'{}' referenced from '{}' does not exist",
+            path.display(),
+            from.display()
+        ))
+        .into());
+    }
+    resolve(path, transform, stack)
+}
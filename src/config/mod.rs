@@ -1,9 +1,13 @@
 // Configuration management module
 
+mod layers;
+
+pub use layers::{document_references, load_document, references};
+
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::fs;
-use std::path::Path;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+use std::path::{Path, PathBuf};
 use thiserror::Error;
 
 #[derive(Debug, Error)]
@@ -12,6 +16,16 @@ pub enum ConfigError {
     FileReadError(#[from] std::io::Error),
     #[error("YAML parse error: {0}")]
     YamlParseError(#[from] serde_yaml::Error),
+    #[error("Invalid config: {0}")]
+    Invalid(String),
+    #[error("Config files extend or include each other in a cycle: {0}")]
+    Cycle(String),
+    #[error("'{key}' is defined differently in '{}' and '{}'", first.display(), second.display())]
+    Conflict {
+        key: String,
+        first: PathBuf,
+        second: PathBuf,
+    },
 }
 
 pub type Result<T> = std::result::Result<T, ConfigError>;
@@ -19,13 +33,187 @@ pub type Result<T> = std::result::Result<T, ConfigError>;
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Config {
     pub org: String,
+    #[serde(default)]
     pub repositories: Vec<String>,
+    /// Rules selecting further repositories, resolved against GitHub at push time
+    #[serde(default, skip_serializing_if = "RepositorySelection::is_empty")]
+    pub select_repositories: RepositorySelection,
     pub env: HashMap<String, String>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub org_secrets: HashMap<String, OrgSecret>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub environments: HashMap<String, Environment>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub vars: HashMap<String, String>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub org_vars: HashMap<String, OrgVariable>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub secret_options: HashMap<String, SecretOptions>,
+    /// Secrets and variables that replace or extend `env` and `vars` for one repository
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub overrides: HashMap<String, RepoOverride>,
+    /// Secret names (glob patterns) that `sync --prune` must never delete
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub protected: Vec<String>,
+}
+
+/// Repositories chosen by name pattern, topic or team rather than listed by hand
+///
+/// A repository is selected when it matches any of `names`, `topics` or
+/// `teams`; `exclude` removes repositories from the final set, including
+/// those listed explicitly in `repositories`. Archived repositories are never
+/// selected.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct RepositorySelection {
+    /// Glob patterns matched against repository names
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub names: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub topics: Vec<String>,
+    /// Team slugs whose repositories are selected
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub teams: Vec<String>,
+    /// Glob patterns of repository names that are never selected
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exclude: Vec<String>,
+}
+
+impl RepositorySelection {
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+            && self.topics.is_empty()
+            && self.teams.is_empty()
+            && self.exclude.is_empty()
+    }
+
+    /// Whether any rule can add repositories, as opposed to only excluding them
+    pub fn selects(&self) -> bool {
+        !self.names.is_empty() || !self.topics.is_empty() || !self.teams.is_empty()
+    }
+}
+
+/// Secrets and variables for a GitHub deployment environment, pushed to every repository
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(bound(deserialize = "V: Deserialize<'de>"))]
+pub struct Environment<V = String> {
+    #[serde(default)]
+    pub env: HashMap<String, V>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub vars: HashMap<String, String>,
+}
+
+/// Repository-specific secrets and variables; entries win over the top-level
+/// `env` and `vars` and may add names those do not declare
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(bound(deserialize = "V: Deserialize<'de>"))]
+pub struct RepoOverride<V = String> {
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub env: HashMap<String, V>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub vars: HashMap<String, String>,
+}
+
+/// Which repositories in the organization can access an org-level secret
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Visibility {
+    All,
+    #[default]
+    Private,
+    Selected,
+}
+
+impl fmt::Display for Visibility {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Visibility::All => "all",
+            Visibility::Private => "private",
+            Visibility::Selected => "selected",
+        };
+        f.write_str(s)
+    }
+}
+
+/// A GitHub secret store that a secret can be pushed to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SecretTarget {
+    Actions,
+    Dependabot,
+    Codespaces,
+}
+
+impl SecretTarget {
+    /// Path segment used by the REST API for this store
+    pub fn api_segment(&self) -> &'static str {
+        match self {
+            SecretTarget::Actions => "actions",
+            SecretTarget::Dependabot => "dependabot",
+            SecretTarget::Codespaces => "codespaces",
+        }
+    }
+}
+
+impl fmt::Display for SecretTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.api_segment())
+    }
+}
+
+/// Secrets land in the Actions store unless told otherwise
+pub const DEFAULT_TARGETS: &[SecretTarget] = &[SecretTarget::Actions];
+
+fn default_targets() -> Vec<SecretTarget> {
+    DEFAULT_TARGETS.to_vec()
+}
+
+fn is_default_targets(targets: &[SecretTarget]) -> bool {
+    targets == DEFAULT_TARGETS
+}
+
+/// Per-secret settings for entries of `env`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SecretOptions {
+    #[serde(
+        default = "default_targets",
+        skip_serializing_if = "is_default_targets"
+    )]
+    pub targets: Vec<SecretTarget>,
+    /// Repository name patterns the secret is limited to; every repository when empty
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub repositories: Vec<String>,
+}
+
+/// An organization-level secret; `V` is the plaintext or encrypted value
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OrgSecret<V = String> {
+    pub value: V,
+    #[serde(default)]
+    pub visibility: Visibility,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub selected_repositories: Vec<String>,
+    #[serde(
+        default = "default_targets",
+        skip_serializing_if = "is_default_targets"
+    )]
+    pub targets: Vec<SecretTarget>,
+}
+
+/// An organization-level Actions variable; always stored in plaintext
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OrgVariable {
+    pub value: String,
+    #[serde(default)]
+    pub visibility: Visibility,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub selected_repositories: Vec<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct EncryptedValue {
-    pub salt: String,
+    /// Per-value salt of files written before file keys were introduced
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub salt: Option<String>,
     pub nonce: String,
     pub ciphertext: String,
 }
@@ -33,12 +221,278 @@ pub struct EncryptedValue {
 #[derive(Debug, Serialize, Deserialize)]
 pub struct EncryptedConfig {
     pub org: String,
+    #[serde(default)]
     pub repositories: Vec<String>,
+    #[serde(default, skip_serializing_if = "RepositorySelection::is_empty")]
+    pub select_repositories: RepositorySelection,
     pub env: HashMap<String, EncryptedValue>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub org_secrets: HashMap<String, OrgSecret<EncryptedValue>>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub environments: HashMap<String, Environment<EncryptedValue>>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub vars: HashMap<String, String>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub org_vars: HashMap<String, OrgVariable>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub secret_options: HashMap<String, SecretOptions>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub overrides: HashMap<String, RepoOverride<EncryptedValue>>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub protected: Vec<String>,
+    /// Metadata of the file, such as the salt its key is derived with
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gsm: Option<serde_yaml::Value>,
+}
+
+impl Config {
+    /// Check constraints that the YAML schema alone cannot express
+    pub fn validate(&self) -> Result<()> {
+        for (name, secret) in &self.org_secrets {
+            check_visibility(
+                "org secret",
+                name,
+                secret.visibility,
+                &secret.selected_repositories,
+            )?;
+            check_targets("org secret", name, &secret.targets)?;
+        }
+        for (name, variable) in &self.org_vars {
+            check_visibility(
+                "org variable",
+                name,
+                variable.visibility,
+                &variable.selected_repositories,
+            )?;
+        }
+        for (name, options) in &self.secret_options {
+            let overridden = self
+                .overrides
+                .values()
+                .any(|repo_override| repo_override.env.contains_key(name));
+            if !self.env.contains_key(name) && !overridden {
+                return Err(ConfigError::Invalid(format!(
+                    "secret_options refers to '{}', which is not defined in env or overrides",
+                    name
+                )));
+            }
+            check_targets("secret", name, &options.targets)?;
+            compile_patterns(&options.repositories)?;
+        }
+        compile_patterns(&self.protected)?;
+        compile_patterns(&self.select_repositories.names)?;
+        compile_patterns(&self.select_repositories.exclude)?;
+        Ok(())
+    }
+
+    /// Secret stores a repository-level secret from `env` should be pushed to
+    pub fn secret_targets(&self, name: &str) -> &[SecretTarget] {
+        self.secret_options
+            .get(name)
+            .map(|options| options.targets.as_slice())
+            .unwrap_or(DEFAULT_TARGETS)
+    }
+
+    /// Repository secret stores the config manages; stores it never mentions
+    /// are left alone by pruning
+    pub fn managed_targets(&self) -> BTreeSet<SecretTarget> {
+        let mut targets = BTreeSet::from([SecretTarget::Actions]);
+        let overridden = self
+            .overrides
+            .values()
+            .flat_map(|repo_override| repo_override.env.keys());
+        for name in self.env.keys().chain(overridden) {
+            targets.extend(self.secret_targets(name));
+        }
+        targets
+    }
+
+    /// Overrides for a repository; GitHub repository names ignore case
+    pub fn repo_override(&self, repo: &str) -> Option<&RepoOverride> {
+        self.overrides
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(repo))
+            .map(|(_, repo_override)| repo_override)
+    }
+
+    /// The secrets a repository receives: `env` entries whose repository
+    /// filter admits it, then its overrides on top
+    pub fn repo_secrets(&self, repo: &str) -> BTreeMap<&str, &str> {
+        let mut secrets: BTreeMap<&str, &str> = self
+            .env
+            .iter()
+            .filter(|(name, _)| self.secret_applies(name, repo))
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+            .collect();
+        if let Some(repo_override) = self.repo_override(repo) {
+            secrets.extend(
+                repo_override
+                    .env
+                    .iter()
+                    .map(|(name, value)| (name.as_str(), value.as_str())),
+            );
+        }
+        secrets
+    }
+
+    /// The variables a repository receives, with its overrides on top
+    pub fn repo_vars(&self, repo: &str) -> BTreeMap<&str, &str> {
+        let mut vars: BTreeMap<&str, &str> = self
+            .vars
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+            .collect();
+        if let Some(repo_override) = self.repo_override(repo) {
+            vars.extend(
+                repo_override
+                    .vars
+                    .iter()
+                    .map(|(name, value)| (name.as_str(), value.as_str())),
+            );
+        }
+        vars
+    }
+
+    /// Whether the `repositories` filter of a secret admits a repository
+    fn secret_applies(&self, name: &str, repo: &str) -> bool {
+        let Some(options) = self.secret_options.get(name) else {
+            return true;
+        };
+        if options.repositories.is_empty() {
+            return true;
+        }
+        // Patterns are checked by `validate`; one that fails to compile matches nothing
+        let patterns: Vec<glob::Pattern> = options
+            .repositories
+            .iter()
+            .filter_map(|pattern| glob::Pattern::new(pattern).ok())
+            .collect();
+        matches_any(&patterns, repo)
+    }
 }
 
+/// Compile glob patterns such as the `protected` list
+pub fn compile_patterns(patterns: &[String]) -> Result<Vec<glob::Pattern>> {
+    patterns
+        .iter()
+        .map(|pattern| {
+            glob::Pattern::new(pattern)
+                .map_err(|e| ConfigError::Invalid(format!("invalid pattern '{}': {}", pattern, e)))
+        })
+        .collect()
+}
+
+/// Whether a secret name matches any protected pattern; GitHub upper-cases
+/// secret names, so matching ignores case
+pub fn is_protected(patterns: &[glob::Pattern], name: &str) -> bool {
+    matches_any(patterns, name)
+}
+
+/// Whether a name matches any pattern, ignoring case like GitHub does for
+/// both secret and repository names
+pub fn matches_any(patterns: &[glob::Pattern], name: &str) -> bool {
+    let options = glob::MatchOptions {
+        case_sensitive: false,
+        ..glob::MatchOptions::new()
+    };
+    patterns
+        .iter()
+        .any(|pattern| pattern.matches_with(name, options))
+}
+
+/// Ensure a secret is pushed to at least one store
+fn check_targets(kind: &str, name: &str, targets: &[SecretTarget]) -> Result<()> {
+    if targets.is_empty() {
+        return Err(ConfigError::Invalid(format!(
+            "{} '{}' has an empty targets list",
+            kind, name
+        )));
+    }
+    Ok(())
+}
+
+/// Ensure `selected_repositories` is given exactly when visibility is `selected`
+fn check_visibility(
+    kind: &str,
+    name: &str,
+    visibility: Visibility,
+    selected_repositories: &[String],
+) -> Result<()> {
+    match visibility {
+        Visibility::Selected if selected_repositories.is_empty() => {
+            Err(ConfigError::Invalid(format!(
+                "{} '{}' has 'selected' visibility but no selected_repositories",
+                kind, name
+            )))
+        }
+        Visibility::All | Visibility::Private if !selected_repositories.is_empty() => {
+            Err(ConfigError::Invalid(format!(
+                "{} '{}' lists selected_repositories but visibility is '{}'",
+                kind, name, visibility
+            )))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Load and validate a raw config, resolving `extends` and `include`
+///
+/// Commands load through `crypto_ops::load_config`, which also accepts
+/// encrypted files; this remains for library users with raw configs only.
 pub fn load_config_from_file<P: AsRef<Path>>(path: P) -> Result<Config> {
-    let content = fs::read_to_string(path)?;
-    let config: Config = serde_yaml::from_str(&content)?;
+    let document = load_document(path.as_ref(), &mut |_, _| Ok::<_, ConfigError>(()))?;
+    config_from_document(document)
+}
+
+/// Build and validate a config from a fully merged document
+pub fn config_from_document(document: serde_yaml::Value) -> Result<Config> {
+    let config: Config = serde_yaml::from_value(document)?;
+    config.validate()?;
     Ok(config)
 }
+
+/// Every secret value of a config document together with its key path,
+/// such as `env.API_KEY` or `org_secrets.SHARED.value`
+pub fn secret_values_mut(
+    document: &mut serde_yaml::Value,
+) -> Vec<(String, &mut serde_yaml::Value)> {
+    fn entries(
+        value: &mut serde_yaml::Value,
+    ) -> impl Iterator<Item = (String, &mut serde_yaml::Value)> {
+        value
+            .as_mapping_mut()
+            .into_iter()
+            .flat_map(|mapping| mapping.iter_mut())
+            .filter_map(|(key, value)| Some((key.as_str()?.to_string(), value)))
+    }
+
+    let mut secrets = Vec::new();
+    for (section, value) in entries(document) {
+        match section.as_str() {
+            "env" => {
+                for (name, secret) in entries(value) {
+                    secrets.push((format!("env.{}", name), secret));
+                }
+            }
+            "org_secrets" => {
+                for (name, org_secret) in entries(value) {
+                    if let Some(secret) = org_secret.get_mut("value") {
+                        secrets.push((format!("org_secrets.{}.value", name), secret));
+                    }
+                }
+            }
+            "environments" | "overrides" => {
+                for (scope, entry) in entries(value) {
+                    let Some(env) = entry.get_mut("env") else {
+                        continue;
+                    };
+                    for (name, secret) in entries(env) {
+                        secrets.push((format!("{}.{}.env.{}", section, scope, name), secret));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    secrets
+}
@@ -1,13 +1,34 @@
 // Encryption/decryption engine module
 
-use aes_gcm::aead::{Aead, KeyInit};
+use aes_gcm::aead::{Aead, KeyInit, Payload};
 use aes_gcm::{Aes256Gcm, Nonce};
+use argon2::{Algorithm, Argon2, Params, Version};
+use hmac::{Hmac, Mac};
 use pbkdf2::pbkdf2_hmac;
 use rand::RngCore;
+use serde::{Deserialize, Serialize};
 use sha2::Sha256;
 use thiserror::Error;
 
-const PBKDF2_ITER: u32 = 100_000;
+mod recipient;
+mod transit;
+pub use recipient::{Identity, generate_key, wrap_key};
+pub use transit::{TransitKey, Vault};
+
+pub const PBKDF2_ITER: u32 = 100_000;
+/// Argon2id defaults, as recommended by RFC 9106 for memory-constrained use
+pub const ARGON2_MEMORY_KIB: u32 = 64 * 1024;
+pub const ARGON2_TIME: u32 = 3;
+pub const ARGON2_PARALLELISM: u32 = 4;
+/// Weakest Argon2id parameters accepted for new files (the OWASP minimum)
+pub const ARGON2_MIN_MEMORY_KIB: u32 = 19 * 1024;
+pub const ARGON2_MIN_TIME: u32 = 2;
+/// Costliest parameters accepted from a file; the key is derived before the
+/// MAC can be checked, so a crafted file must not exhaust memory or time
+const ARGON2_MAX_MEMORY_KIB: u32 = 4 * 1024 * 1024;
+const ARGON2_MAX_TIME: u32 = 64;
+const ARGON2_MAX_PARALLELISM: u32 = 64;
+const PBKDF2_MAX_ITER: u32 = 10_000_000;
 const SALT_LEN: usize = 16;
 const NONCE_LEN: usize = 12;
 const KEY_LEN: usize = 32;
@@ -20,36 +41,211 @@ pub enum CryptoError {
     EncryptionFailed(String),
     #[error("Decryption failed: {0}")]
     DecryptionFailed(String),
+    #[error("Integrity check failed: {0}")]
+    IntegrityCheckFailed(String),
+    #[error("Vault Transit error: {0}")]
+    Transit(String),
 }
 
 pub type Result<T> = std::result::Result<T, CryptoError>;
 
-pub fn derive_key(password: &[u8], salt: &[u8]) -> [u8; KEY_LEN] {
+pub type Key = [u8; KEY_LEN];
+
+/// Key derivation function and its parameters, as recorded in encrypted files
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "name", rename_all = "kebab-case")]
+pub enum Kdf {
+    Argon2id {
+        memory_kib: u32,
+        time: u32,
+        parallelism: u32,
+    },
+    Pbkdf2Sha256 {
+        iterations: u32,
+    },
+}
+
+impl Default for Kdf {
+    fn default() -> Self {
+        Kdf::Argon2id {
+            memory_kib: ARGON2_MEMORY_KIB,
+            time: ARGON2_TIME,
+            parallelism: ARGON2_PARALLELISM,
+        }
+    }
+}
+
+impl Kdf {
+    /// Refuse parameters too weak for writing new files
+    pub fn check_strength(&self) -> Result<()> {
+        let weak = match *self {
+            Kdf::Argon2id {
+                memory_kib, time, ..
+            } if memory_kib < ARGON2_MIN_MEMORY_KIB || time < ARGON2_MIN_TIME => format!(
+                "Argon2id needs at least {} KiB of memory and {} passes",
+                ARGON2_MIN_MEMORY_KIB, ARGON2_MIN_TIME
+            ),
+            Kdf::Pbkdf2Sha256 { iterations } if iterations < PBKDF2_ITER => {
+                format!("PBKDF2 needs at least {} iterations", PBKDF2_ITER)
+            }
+            _ => return Ok(()),
+        };
+        Err(CryptoError::KeyError(weak))
+    }
+
+    /// Refuse parameters read from a file that would take unreasonable
+    /// memory or time to derive a key with
+    pub fn check_cost(&self) -> Result<()> {
+        let costly = match *self {
+            Kdf::Argon2id {
+                memory_kib,
+                time,
+                parallelism,
+            } => {
+                memory_kib > ARGON2_MAX_MEMORY_KIB
+                    || time > ARGON2_MAX_TIME
+                    || parallelism > ARGON2_MAX_PARALLELISM
+            }
+            Kdf::Pbkdf2Sha256 { iterations } => iterations > PBKDF2_MAX_ITER,
+        };
+        if costly {
+            return Err(CryptoError::KeyError(format!(
+                "the file asks for key derivation costlier than gsm allows: {:?}",
+                self
+            )));
+        }
+        Ok(())
+    }
+
+    pub fn derive(&self, password: &[u8], salt: &[u8]) -> Result<Key> {
+        match *self {
+            Kdf::Argon2id {
+                memory_kib,
+                time,
+                parallelism,
+            } => {
+                let params =
+                    Params::new(memory_kib, time, parallelism, Some(KEY_LEN)).map_err(|e| {
+                        CryptoError::KeyError(format!("invalid Argon2id parameters: {}", e))
+                    })?;
+                let mut key = [0u8; KEY_LEN];
+                Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+                    .hash_password_into(password, salt, &mut key)
+                    .map_err(|e| CryptoError::KeyError(e.to_string()))?;
+                Ok(key)
+            }
+            Kdf::Pbkdf2Sha256 { iterations } => {
+                if iterations == 0 {
+                    return Err(CryptoError::KeyError(
+                        "PBKDF2 needs at least one iteration".to_string(),
+                    ));
+                }
+                let mut key = [0u8; KEY_LEN];
+                pbkdf2_hmac::<Sha256>(password, salt, iterations, &mut key);
+                Ok(key)
+            }
+        }
+    }
+}
+
+/// Cipher used for the values of encrypted files
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub enum Cipher {
+    #[default]
+    #[serde(rename = "aes-256-gcm")]
+    Aes256Gcm,
+}
+
+pub fn derive_key(password: &[u8], salt: &[u8]) -> Key {
     let mut key = [0u8; KEY_LEN];
     pbkdf2_hmac::<Sha256>(password, salt, PBKDF2_ITER, &mut key);
     key
 }
 
-pub fn encrypt(plaintext: &[u8], password: &[u8]) -> Result<(Vec<u8>, Vec<u8>, Vec<u8>)> {
+/// Derive an independent key for `purpose` from a file key
+pub fn subkey(key: &Key, purpose: &str) -> Key {
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(key).expect("HMAC takes keys of any size");
+    mac.update(purpose.as_bytes());
+    mac.finalize().into_bytes().into()
+}
+
+/// HMAC-SHA256 of `data`
+pub fn mac(key: &Key, data: &[u8]) -> Vec<u8> {
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(key).expect("HMAC takes keys of any size");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// Check an HMAC-SHA256 tag in constant time
+pub fn verify_mac(key: &Key, data: &[u8], tag: &[u8]) -> Result<()> {
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(key).expect("HMAC takes keys of any size");
+    mac.update(data);
+    mac.verify_slice(tag).map_err(|_| {
+        CryptoError::IntegrityCheckFailed(
+            "the file was changed outside gsm, or the key is wrong".to_string(),
+        )
+    })
+}
+
+/// Generate a random salt for deriving a key
+pub fn generate_salt() -> Vec<u8> {
     let mut salt = [0u8; SALT_LEN];
     rand::rng().fill_bytes(&mut salt);
-    let key = derive_key(password, &salt);
+    salt.to_vec()
+}
+
+/// Encrypt with an already derived key, returning (nonce, ciphertext)
+///
+/// `aad` is authenticated but not encrypted; decryption must pass the same bytes.
+pub fn encrypt_with_key(plaintext: &[u8], key: &Key, aad: &[u8]) -> Result<(Vec<u8>, Vec<u8>)> {
     let cipher =
-        Aes256Gcm::new_from_slice(&key).map_err(|e| CryptoError::KeyError(format!("{:?}", e)))?;
+        Aes256Gcm::new_from_slice(key).map_err(|e| CryptoError::KeyError(format!("{:?}", e)))?;
     let mut nonce = [0u8; NONCE_LEN];
     rand::rng().fill_bytes(&mut nonce);
     let ciphertext = cipher
-        .encrypt(Nonce::from_slice(&nonce), plaintext)
+        .encrypt(
+            Nonce::from_slice(&nonce),
+            Payload {
+                msg: plaintext,
+                aad,
+            },
+        )
         .map_err(|e| CryptoError::EncryptionFailed(e.to_string()))?;
-    Ok((salt.to_vec(), nonce.to_vec(), ciphertext))
+    Ok((nonce.to_vec(), ciphertext))
 }
 
-pub fn decrypt(ciphertext: &[u8], password: &[u8], salt: &[u8], nonce: &[u8]) -> Result<Vec<u8>> {
-    let key = derive_key(password, salt);
+/// Decrypt with an already derived key
+pub fn decrypt_with_key(ciphertext: &[u8], key: &Key, nonce: &[u8], aad: &[u8]) -> Result<Vec<u8>> {
+    if nonce.len() != NONCE_LEN {
+        return Err(CryptoError::DecryptionFailed(format!(
+            "nonce must be {} bytes, got {}",
+            NONCE_LEN,
+            nonce.len()
+        )));
+    }
     let cipher =
-        Aes256Gcm::new_from_slice(&key).map_err(|e| CryptoError::KeyError(format!("{:?}", e)))?;
+        Aes256Gcm::new_from_slice(key).map_err(|e| CryptoError::KeyError(format!("{:?}", e)))?;
     let plaintext = cipher
-        .decrypt(Nonce::from_slice(nonce), ciphertext)
+        .decrypt(
+            Nonce::from_slice(nonce),
+            Payload {
+                msg: ciphertext,
+                aad,
+            },
+        )
         .map_err(|e| CryptoError::DecryptionFailed(e.to_string()))?;
     Ok(plaintext)
 }
+
+/// Encrypt a value under a key derived from `password` and a fresh salt,
+/// returning (salt, nonce, ciphertext), as values were written before file keys
+pub fn encrypt(plaintext: &[u8], password: &[u8]) -> Result<(Vec<u8>, Vec<u8>, Vec<u8>)> {
+    let salt = generate_salt();
+    let (nonce, ciphertext) = encrypt_with_key(plaintext, &derive_key(password, &salt), &[])?;
+    Ok((salt, nonce, ciphertext))
+}
+
+/// Decrypt a value that carries its own salt, as written before file keys
+pub fn decrypt(ciphertext: &[u8], password: &[u8], salt: &[u8], nonce: &[u8]) -> Result<Vec<u8>> {
+    decrypt_with_key(ciphertext, &derive_key(password, salt), nonce, &[])
+}
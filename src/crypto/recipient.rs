@@ -0,0 +1,76 @@
+// X25519 recipients: file keys sealed to the public keys of team members
+
+use super::{CryptoError, KEY_LEN, Key, Result};
+use rand::RngCore;
+use sodiumoxide::crypto::box_::{self, PublicKey, SecretKey};
+use sodiumoxide::crypto::sealedbox;
+
+fn init() -> Result<()> {
+    sodiumoxide::init()
+        .map_err(|_| CryptoError::KeyError("failed to initialize libsodium".to_string()))
+}
+
+/// Generate a random key for a file that is shared with recipients
+pub fn generate_key() -> Key {
+    let mut key = [0u8; KEY_LEN];
+    rand::rng().fill_bytes(&mut key);
+    key
+}
+
+/// Seal a file key to a recipient's public key
+pub fn wrap_key(key: &Key, public_key: &[u8]) -> Result<Vec<u8>> {
+    init()?;
+    let public_key = PublicKey::from_slice(public_key).ok_or_else(|| {
+        CryptoError::KeyError(format!(
+            "a recipient public key must be {} bytes",
+            box_::PUBLICKEYBYTES
+        ))
+    })?;
+    Ok(sealedbox::seal(key, &public_key))
+}
+
+/// An X25519 key pair that file keys can be sealed to
+pub struct Identity {
+    public: PublicKey,
+    secret: SecretKey,
+}
+
+impl Identity {
+    pub fn generate() -> Result<Identity> {
+        init()?;
+        let (public, secret) = box_::gen_keypair();
+        Ok(Identity { public, secret })
+    }
+
+    pub fn from_secret_key(secret_key: &[u8]) -> Result<Identity> {
+        init()?;
+        let secret = SecretKey::from_slice(secret_key).ok_or_else(|| {
+            CryptoError::KeyError(format!(
+                "an identity secret key must be {} bytes",
+                box_::SECRETKEYBYTES
+            ))
+        })?;
+        Ok(Identity {
+            public: secret.public_key(),
+            secret,
+        })
+    }
+
+    pub fn public_key(&self) -> &[u8] {
+        self.public.as_ref()
+    }
+
+    pub fn secret_key(&self) -> &[u8] {
+        &self.secret.0
+    }
+
+    /// Open a file key sealed to this identity
+    pub fn unwrap_key(&self, sealed: &[u8]) -> Result<Key> {
+        let key = sealedbox::open(sealed, &self.public, &self.secret).map_err(|_| {
+            CryptoError::DecryptionFailed("the file key is not sealed to this identity".to_string())
+        })?;
+        key.try_into().map_err(|_| {
+            CryptoError::DecryptionFailed("the file key has the wrong length".to_string())
+        })
+    }
+}
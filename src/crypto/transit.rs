@@ -0,0 +1,171 @@
+// Vault Transit envelope encryption: file keys wrapped by a key that never
+// leaves Vault, or any server implementing the same `encrypt` and `decrypt`
+// endpoints
+
+use super::{CryptoError, KEY_LEN, Key, Result};
+use base64::{Engine as _, engine::general_purpose};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+fn default_mount() -> String {
+    "transit".to_string()
+}
+
+/// A named key in a Transit secrets engine
+///
+/// Which Vault holds it is up to the user, see [`Vault`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TransitKey {
+    /// Mount path of the secrets engine
+    #[serde(default = "default_mount")]
+    pub mount: String,
+    pub key: String,
+}
+
+/// Where and as whom to reach Vault
+///
+/// The address never comes from the files being encrypted or decrypted:
+/// a file naming its own Vault could have the token sent anywhere.
+#[derive(Debug, Clone)]
+pub struct Vault {
+    address: String,
+    token: String,
+}
+
+impl Vault {
+    /// Refuses addresses that would send the token in the clear, unless
+    /// `allow_http` opts in to plain `http://`
+    pub fn new(address: &str, token: &str, allow_http: bool) -> Result<Vault> {
+        let http = address.starts_with("http://");
+        let allowed = address.starts_with("https://") || (http && allow_http);
+        if !allowed {
+            let hint = if http {
+                "; allow plain HTTP with --vault-allow-http"
+            } else {
+                ""
+            };
+            return Err(CryptoError::Transit(format!(
+                "refusing to send the Vault token to '{}': the address must use https{}",
+                address, hint
+            )));
+        }
+        Ok(Vault {
+            address: address.trim_end_matches('/').to_string(),
+            token: token.to_string(),
+        })
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct TransitResponse {
+    data: TransitData,
+}
+
+#[derive(Debug, Deserialize)]
+struct TransitData {
+    ciphertext: Option<String>,
+    plaintext: Option<String>,
+}
+
+impl TransitKey {
+    /// Wrap a file key, returning Vault's ciphertext such as `vault:v1:...`
+    pub fn wrap_key(&self, key: &Key, vault: &Vault) -> Result<String> {
+        let body = json!({ "plaintext": general_purpose::STANDARD.encode(key) });
+        self.post("encrypt", vault, body)?
+            .ciphertext
+            .ok_or_else(|| CryptoError::Transit("the response has no ciphertext".to_string()))
+    }
+
+    /// Unwrap a file key wrapped by [`TransitKey::wrap_key`]
+    pub fn unwrap_key(&self, wrapped: &str, vault: &Vault) -> Result<Key> {
+        let plaintext = self
+            .post("decrypt", vault, json!({ "ciphertext": wrapped }))?
+            .plaintext
+            .ok_or_else(|| CryptoError::Transit("the response has no plaintext".to_string()))?;
+        let key = general_purpose::STANDARD
+            .decode(plaintext)
+            .map_err(|e| CryptoError::Transit(format!("invalid plaintext: {}", e)))?;
+        key.try_into().map_err(|_| {
+            CryptoError::Transit(format!("the unwrapped key is not {} bytes", KEY_LEN))
+        })
+    }
+
+    /// Refuse mounts and key names that are not plain path segments
+    ///
+    /// Both come from files a repository controls, so `..` or an extra `/`
+    /// could otherwise point the token at any endpoint of the user's Vault.
+    pub fn check(&self) -> Result<()> {
+        let mount = self.mount.trim_matches('/');
+        if !mount.split('/').all(is_name) {
+            return Err(CryptoError::Transit(format!(
+                "invalid mount '{}': use letters, digits, '-' and '_' separated by '/'",
+                self.mount
+            )));
+        }
+        if !is_name(&self.key) {
+            return Err(CryptoError::Transit(format!(
+                "invalid key name '{}': use letters, digits, '-' and '_'",
+                self.key
+            )));
+        }
+        Ok(())
+    }
+
+    fn post(&self, operation: &str, vault: &Vault, body: serde_json::Value) -> Result<TransitData> {
+        self.check()?;
+        let url = format!(
+            "{}/v1/{}/{}/{}",
+            vault.address,
+            self.mount.trim_matches('/'),
+            operation,
+            self.key
+        );
+
+        // Callers are synchronous but may run inside gsm's async runtime,
+        // where blocking on another runtime panics, so the request gets a
+        // thread and runtime of its own
+        std::thread::scope(|scope| {
+            scope
+                .spawn(|| {
+                    tokio::runtime::Builder::new_current_thread()
+                        .enable_all()
+                        .build()
+                        .map_err(|e| CryptoError::Transit(e.to_string()))?
+                        .block_on(send(&url, &vault.token, &body))
+                })
+                .join()
+                .unwrap_or_else(|_| Err(CryptoError::Transit("the request panicked".to_string())))
+        })
+    }
+}
+
+/// Whether a path segment is a plain name, which rules out `.` and `..`
+fn is_name(segment: &str) -> bool {
+    !segment.is_empty()
+        && segment
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+async fn send(url: &str, token: &str, body: &serde_json::Value) -> Result<TransitData> {
+    let failed = |e: reqwest::Error| CryptoError::Transit(format!("POST {}: {}", url, e));
+    let response = reqwest::Client::new()
+        .post(url)
+        .header("X-Vault-Token", token)
+        .json(body)
+        .send()
+        .await
+        .map_err(failed)?;
+    let status = response.status();
+    if !status.is_success() {
+        let text = response.text().await.unwrap_or_default();
+        return Err(CryptoError::Transit(format!(
+            "POST {} returned {}: {}",
+            url,
+            status,
+            text.trim()
+        )));
+    }
+    let response: TransitResponse = response.json().await.map_err(failed)?;
+    Ok(response.data)
+}
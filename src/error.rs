@@ -3,6 +3,7 @@ use thiserror::Error;
 use crate::config::ConfigError;
 use crate::crypto::CryptoError;
 use crate::github::GithubError;
+use crate::plan::PlanError;
 
 #[derive(Debug, Error)]
 pub enum GsmError {
@@ -12,12 +13,16 @@ pub enum GsmError {
     Crypto(#[from] CryptoError),
     #[error(transparent)]
     Github(#[from] GithubError),
+    #[error(transparent)]
+    Plan(#[from] PlanError),
     #[error("IO error: {0}")]
     Io(#[from] std::io::Error),
     #[error("Env var error: {0}")]
     EnvVar(#[from] std::env::VarError),
     #[error("YAML error: {0}")]
     Yaml(#[from] serde_yaml::Error),
+    #[error("JSON error: {0}")]
+    Json(#[from] serde_json::Error),
     #[error("Base64 error: {0}")]
     Base64(#[from] base64::DecodeError),
     #[error("UTF8 error: {0}")]
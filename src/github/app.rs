@@ -0,0 +1,133 @@
+// GitHub App authentication: app JWTs and installation access tokens
+
+use super::{GithubError, Result, http_error};
+use jsonwebtoken::{Algorithm, EncodingKey, Header};
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Installation tokens are valid for an hour; refresh well before that
+const TOKEN_LIFETIME: Duration = Duration::from_secs(50 * 60);
+
+/// Credentials of a GitHub App installed on an organization
+pub struct GithubApp {
+    app_id: String,
+    key: EncodingKey,
+    org: String,
+    installation_id: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+struct Claims {
+    iat: u64,
+    exp: u64,
+    iss: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Installation {
+    id: u64,
+}
+
+#[derive(Debug, Deserialize)]
+struct AccessToken {
+    token: String,
+}
+
+/// An installation access token and when it should be replaced
+pub(super) struct InstallationToken {
+    pub(super) token: String,
+    refresh_at: Instant,
+}
+
+impl InstallationToken {
+    pub(super) fn is_fresh(&self) -> bool {
+        Instant::now() < self.refresh_at
+    }
+}
+
+impl GithubApp {
+    /// Create app credentials from an app ID and a PEM encoded RSA private key
+    ///
+    /// Without an installation ID, the installation is looked up from `org`.
+    pub fn new(
+        app_id: String,
+        private_key_pem: &[u8],
+        org: String,
+        installation_id: Option<u64>,
+    ) -> Result<Self> {
+        let key = EncodingKey::from_rsa_pem(private_key_pem)
+            .map_err(|e| GithubError::AppAuth(format!("invalid private key: {}", e)))?;
+        Ok(GithubApp {
+            app_id,
+            key,
+            org,
+            installation_id,
+        })
+    }
+
+    /// Sign a short-lived JWT identifying the app itself
+    pub fn jwt(&self) -> Result<String> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        // Backdate to allow for clock drift; GitHub caps the lifetime at ten minutes
+        let claims = Claims {
+            iat: now.saturating_sub(60),
+            exp: now + 9 * 60,
+            iss: self.app_id.clone(),
+        };
+        jsonwebtoken::encode(&Header::new(Algorithm::RS256), &claims, &self.key)
+            .map_err(|e| GithubError::AppAuth(format!("failed to sign JWT: {}", e)))
+    }
+
+    /// Exchange the app JWT for an access token of the org installation
+    pub(super) async fn installation_token(
+        &self,
+        client: &reqwest::Client,
+        base_url: &str,
+    ) -> Result<InstallationToken> {
+        let jwt = self.jwt()?;
+        let installation_id = match self.installation_id {
+            Some(id) => id,
+            None => {
+                let resp = client
+                    .get(format!("{}/orgs/{}/installation", base_url, self.org))
+                    .bearer_auth(&jwt)
+                    .header("User-Agent", "gsm-cli")
+                    .send()
+                    .await?;
+                if !resp.status().is_success() {
+                    let error = http_error(resp).await;
+                    return Err(GithubError::AppAuth(format!(
+                        "no installation found for org {}: {}",
+                        self.org, error
+                    )));
+                }
+                resp.json::<Installation>().await?.id
+            }
+        };
+
+        let resp = client
+            .post(format!(
+                "{}/app/installations/{}/access_tokens",
+                base_url, installation_id
+            ))
+            .bearer_auth(&jwt)
+            .header("User-Agent", "gsm-cli")
+            .send()
+            .await?;
+        if !resp.status().is_success() {
+            let error = http_error(resp).await;
+            return Err(GithubError::AppAuth(format!(
+                "failed to create installation token: {}",
+                error
+            )));
+        }
+        let access_token: AccessToken = resp.json().await?;
+        Ok(InstallationToken {
+            token: access_token.token,
+            refresh_at: Instant::now() + TOKEN_LIFETIME,
+        })
+    }
+}
@@ -1,8 +1,18 @@
 // GitHub API integration module
 
+mod app;
+
+pub use app::GithubApp;
+
+use crate::config::{SecretTarget, Visibility};
+use app::InstallationToken;
 use base64::{Engine as _, engine::general_purpose};
+use colored::Colorize;
+use rand::Rng;
 use serde::{Deserialize, Serialize};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use thiserror::Error;
+use tokio::sync::{Mutex, Semaphore};
 
 #[derive(Debug, Error)]
 pub enum GithubError {
@@ -18,6 +28,10 @@ pub enum GithubError {
     InvalidPublicKeyLength,
     #[error("Reqwest error: {0}")]
     ReqwestError(#[from] reqwest::Error),
+    #[error("Rate limited after {0} retries: {1}")]
+    RateLimited(u32, String),
+    #[error("GitHub App authentication failed: {0}")]
+    AppAuth(String),
 }
 
 pub type Result<T> = std::result::Result<T, GithubError>;
@@ -28,77 +42,713 @@ pub struct PublicKey {
     pub key_id: String,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct Repository {
+    pub id: u64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Variable {
+    pub value: String,
+    #[serde(default)]
+    pub visibility: Option<Visibility>,
+}
+
+/// A repository as listed for an organization or team
+#[derive(Debug, Deserialize)]
+pub struct OrgRepository {
+    pub name: String,
+    #[serde(default)]
+    pub topics: Vec<String>,
+    #[serde(default)]
+    pub archived: bool,
+}
+
+/// A secret as listed by the API; values are never returned
+#[derive(Debug, Deserialize)]
+pub struct SecretInfo {
+    pub name: String,
+    pub updated_at: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct SecretList {
+    secrets: Vec<SecretInfo>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RepositoryList {
+    repositories: Vec<Repository>,
+}
+
+/// Where an Actions configuration variable lives
+#[derive(Debug, Clone, Copy)]
+pub enum VariableScope<'a> {
+    Repo {
+        org: &'a str,
+        repo: &'a str,
+    },
+    Environment {
+        org: &'a str,
+        repo: &'a str,
+        environment: &'a str,
+    },
+    Org {
+        org: &'a str,
+    },
+}
+
+impl VariableScope<'_> {
+    /// API path of the variables collection for this scope
+    fn path(&self) -> String {
+        match self {
+            VariableScope::Repo { org, repo } => {
+                format!("/repos/{}/{}/actions/variables", org, repo)
+            }
+            VariableScope::Environment {
+                org,
+                repo,
+                environment,
+            } => format!(
+                "/repos/{}/{}/environments/{}/variables",
+                org,
+                repo,
+                encode_path_segment(environment)
+            ),
+            VariableScope::Org { org } => format!("/orgs/{}/actions/variables", org),
+        }
+    }
+}
+
+/// Page size used for paginated listings (the API maximum)
+const PER_PAGE: usize = 100;
+
+/// Requests allowed in flight at once unless configured otherwise
+pub const DEFAULT_CONCURRENCY: usize = 8;
+
+/// How failed requests are retried
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    /// Retries after the first attempt; zero disables retrying
+    pub max_retries: u32,
+    /// Delay before the first retry, doubled on every further attempt
+    pub base_delay: Duration,
+    /// Upper bound for any retry delay; a longer wait requested through
+    /// `Retry-After` or `x-ratelimit-reset` fails the request instead
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_retries: 5,
+            base_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(60),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Exponential backoff with jitter for the given retry attempt (0-based);
+    /// the result lies between half and all of the capped exponential delay
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let exponential = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(attempt))
+            .min(self.max_delay);
+        exponential.mul_f64(rand::rng().random_range(0.5..=1.0))
+    }
+
+    /// Delay before retrying a response, or `None` if it should not be retried;
+    /// server errors are only retried for requests that are safe to repeat
+    fn delay_for(
+        &self,
+        resp: &reqwest::Response,
+        attempt: u32,
+        idempotent: bool,
+    ) -> Option<Duration> {
+        let status = resp.status();
+        let headers = resp.headers();
+        let header = |name: &str| {
+            headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.trim().parse::<u64>().ok())
+        };
+
+        let rate_limited = status == reqwest::StatusCode::TOO_MANY_REQUESTS
+            || (status == reqwest::StatusCode::FORBIDDEN
+                && (header("retry-after").is_some() || header("x-ratelimit-remaining") == Some(0)));
+        if rate_limited {
+            if let Some(seconds) = header("retry-after") {
+                return Some(Duration::from_secs(seconds));
+            }
+            if header("x-ratelimit-remaining") == Some(0)
+                && let Some(reset) = header("x-ratelimit-reset")
+            {
+                let now = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs();
+                return Some(Duration::from_secs(reset.saturating_sub(now) + 1));
+            }
+            return Some(self.backoff(attempt));
+        }
+        if idempotent && status.is_server_error() && status != reqwest::StatusCode::NOT_IMPLEMENTED
+        {
+            return Some(self.backoff(attempt));
+        }
+        None
+    }
+}
+
+/// How requests are authenticated
+enum Auth {
+    Token(String),
+    /// Installation tokens are fetched on first use and replaced when they expire
+    App {
+        app: GithubApp,
+        token: Mutex<Option<InstallationToken>>,
+    },
+}
+
 /// GitHub API client for managing repositories and secrets
 pub struct GithubClient {
     client: reqwest::Client,
-    token: String,
+    auth: Auth,
     base_url: String,
+    concurrency: usize,
+    limiter: Semaphore,
+    retry_policy: RetryPolicy,
 }
 
 impl GithubClient {
     /// Create a new GitHub client
     pub fn new(token: String, base_url: Option<String>) -> Self {
+        Self::with_auth(Auth::Token(token), base_url)
+    }
+
+    /// Create a GitHub client authenticated as a GitHub App installation
+    pub fn from_app(app: GithubApp, base_url: Option<String>) -> Self {
+        Self::with_auth(
+            Auth::App {
+                app,
+                token: Mutex::new(None),
+            },
+            base_url,
+        )
+    }
+
+    fn with_auth(auth: Auth, base_url: Option<String>) -> Self {
         GithubClient {
             client: reqwest::Client::new(),
-            token,
+            auth,
             base_url: base_url.unwrap_or_else(|| "https://api.github.com".to_string()),
+            concurrency: DEFAULT_CONCURRENCY,
+            limiter: Semaphore::new(DEFAULT_CONCURRENCY),
+            retry_policy: RetryPolicy::default(),
         }
     }
 
-    /// Get the public key for a repository
-    pub async fn get_repo_public_key(&self, org: &str, repo: &str) -> Result<PublicKey> {
-        let url = format!(
-            "{}/repos/{}/{}/actions/secrets/public-key",
-            self.base_url, org, repo
-        );
-        let resp = self
-            .client
-            .get(&url)
-            .header("Authorization", format!("token {}", self.token))
+    /// Set how rate-limited and transiently failing requests are retried
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    /// Limit how many requests this client sends at the same time
+    pub fn with_concurrency(mut self, concurrency: usize) -> Self {
+        let concurrency = concurrency.max(1);
+        self.concurrency = concurrency;
+        self.limiter = Semaphore::new(concurrency);
+        self
+    }
+
+    /// Maximum number of requests in flight at once
+    pub fn concurrency(&self) -> usize {
+        self.concurrency
+    }
+
+    /// Build a request against the API; credentials are added when it is sent
+    fn request(&self, method: reqwest::Method, path: &str) -> reqwest::RequestBuilder {
+        self.client
+            .request(method, format!("{}{}", self.base_url, path))
             .header("User-Agent", "gsm-cli")
-            .send()
-            .await?;
+    }
+
+    /// Value of the `Authorization` header, fetching an installation token if needed
+    async fn authorization(&self) -> Result<String> {
+        match &self.auth {
+            Auth::Token(token) => Ok(format!("token {}", token)),
+            Auth::App { app, token } => {
+                let mut token = token.lock().await;
+                if let Some(current) = token.as_ref().filter(|current| current.is_fresh()) {
+                    return Ok(format!("token {}", current.token));
+                }
+                let fresh = app.installation_token(&self.client, &self.base_url).await?;
+                let header = format!("token {}", fresh.token);
+                *token = Some(fresh);
+                Ok(header)
+            }
+        }
+    }
+
+    /// Drop a cached installation token, returning whether there was one to drop
+    async fn invalidate_token(&self) -> bool {
+        match &self.auth {
+            Auth::Token(_) => false,
+            Auth::App { token, .. } => token.lock().await.take().is_some(),
+        }
+    }
+
+    /// Send a request, retrying rate limits, transient server errors and
+    /// connection failures according to the retry policy
+    async fn execute(&self, request: reqwest::RequestBuilder) -> Result<reqwest::Response> {
+        // A POST that failed with a server error or timed out may still have
+        // taken effect, so repeating it could create a resource twice
+        let idempotent = request
+            .try_clone()
+            .and_then(|request| request.build().ok())
+            .is_some_and(|request| request.method() != reqwest::Method::POST);
+        let mut attempt = 0;
+        let mut reauthenticated = false;
+        loop {
+            let authorization = self.authorization().await?;
+            // Bodies are always JSON, so requests can be cloned for a retry
+            let Some(this_try) = request.try_clone() else {
+                let _permit = self.limiter.acquire().await;
+                return Ok(request
+                    .header("Authorization", authorization)
+                    .send()
+                    .await?);
+            };
+            let result = {
+                let _permit = self.limiter.acquire().await;
+                this_try.header("Authorization", authorization).send().await
+            };
+            let can_retry = attempt < self.retry_policy.max_retries;
+
+            // An installation token revoked or expired early is replaced once
+            if let Ok(resp) = &result
+                && resp.status() == reqwest::StatusCode::UNAUTHORIZED
+                && !reauthenticated
+                && self.invalidate_token().await
+            {
+                reauthenticated = true;
+                continue;
+            }
+
+            let delay = match result {
+                Ok(resp) => match self.retry_policy.delay_for(&resp, attempt, idempotent) {
+                    Some(delay) if delay > self.retry_policy.max_delay => {
+                        return Err(GithubError::RateLimited(
+                            attempt,
+                            format!(
+                                "GitHub asked to wait {}s, longer than the maximum retry delay of {}s",
+                                delay.as_secs(),
+                                self.retry_policy.max_delay.as_secs()
+                            ),
+                        ));
+                    }
+                    Some(delay) if can_retry => {
+                        eprintln!(
+                            "{}: GitHub returned {}, retrying in {:.1}s ({}/{})",
+                            "Warning".yellow(),
+                            resp.status(),
+                            delay.as_secs_f64(),
+                            attempt + 1,
+                            self.retry_policy.max_retries
+                        );
+                        delay
+                    }
+                    Some(_) if !resp.status().is_server_error() => {
+                        return Err(GithubError::RateLimited(attempt, resp.text().await?));
+                    }
+                    _ => return Ok(resp),
+                },
+                Err(e) if can_retry && (e.is_connect() || (idempotent && e.is_timeout())) => {
+                    let delay = self.retry_policy.backoff(attempt);
+                    eprintln!(
+                        "{}: {}, retrying in {:.1}s ({}/{})",
+                        "Warning".yellow(),
+                        e,
+                        delay.as_secs_f64(),
+                        attempt + 1,
+                        self.retry_policy.max_retries
+                    );
+                    delay
+                }
+                Err(e) => return Err(e.into()),
+            };
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
 
+    /// Send a request, turning non-success statuses into errors
+    async fn send(&self, request: reqwest::RequestBuilder) -> Result<reqwest::Response> {
+        let resp = self.execute(request).await?;
         if !resp.status().is_success() {
-            return Err(GithubError::HttpError(resp.text().await?));
+            return Err(http_error(resp).await);
         }
+        Ok(resp)
+    }
 
+    /// Send a request, returning `None` when the resource does not exist
+    async fn send_optional(
+        &self,
+        request: reqwest::RequestBuilder,
+    ) -> Result<Option<reqwest::Response>> {
+        let resp = self.execute(request).await?;
+        if resp.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        if !resp.status().is_success() {
+            return Err(http_error(resp).await);
+        }
+        Ok(Some(resp))
+    }
+
+    /// Get the public key of a repository secret store
+    pub async fn get_repo_public_key(
+        &self,
+        target: SecretTarget,
+        org: &str,
+        repo: &str,
+    ) -> Result<PublicKey> {
+        let path = format!(
+            "/repos/{}/{}/{}/secrets/public-key",
+            org,
+            repo,
+            target.api_segment()
+        );
+        let resp = self.send(self.request(reqwest::Method::GET, &path)).await?;
         let public_key: PublicKey = resp.json().await?;
         Ok(public_key)
     }
 
-    /// Push a secret to a repository
+    /// Push a secret to a repository secret store
     pub async fn push_repo_secret(
         &self,
+        target: SecretTarget,
         org: &str,
         repo: &str,
         secret_name: &str,
         encrypted_value: &str,
         key_id: &str,
     ) -> Result<()> {
-        let url = format!(
-            "{}/repos/{}/{}/actions/secrets/{}",
-            self.base_url, org, repo, secret_name
+        let path = format!(
+            "/repos/{}/{}/{}/secrets/{}",
+            org,
+            repo,
+            target.api_segment(),
+            secret_name
         );
         let body = SecretBody {
             encrypted_value,
             key_id,
         };
+        self.send(self.request(reqwest::Method::PUT, &path).json(&body))
+            .await?;
+        Ok(())
+    }
+
+    /// List all secrets in a repository secret store, following pagination
+    pub async fn list_repo_secrets(
+        &self,
+        target: SecretTarget,
+        org: &str,
+        repo: &str,
+    ) -> Result<Vec<SecretInfo>> {
+        let path = format!("/repos/{}/{}/{}/secrets", org, repo, target.api_segment());
+        self.list_secrets(&path).await
+    }
+
+    /// Delete a secret from a repository secret store
+    pub async fn delete_repo_secret(
+        &self,
+        target: SecretTarget,
+        org: &str,
+        repo: &str,
+        secret_name: &str,
+    ) -> Result<()> {
+        let path = format!(
+            "/repos/{}/{}/{}/secrets/{}",
+            org,
+            repo,
+            target.api_segment(),
+            secret_name
+        );
+        self.send(self.request(reqwest::Method::DELETE, &path))
+            .await?;
+        Ok(())
+    }
+
+    /// Fetch every page of a secrets listing endpoint
+    async fn list_secrets(&self, path: &str) -> Result<Vec<SecretInfo>> {
+        self.list_pages(path, |list: SecretList| list.secrets).await
+    }
+
+    /// List every repository of an organization
+    pub async fn list_org_repositories(&self, org: &str) -> Result<Vec<OrgRepository>> {
+        self.list_pages(&format!("/orgs/{}/repos", org), |list: Vec<_>| list)
+            .await
+    }
+
+    /// List the repositories a team has access to
+    pub async fn list_team_repositories(
+        &self,
+        org: &str,
+        team_slug: &str,
+    ) -> Result<Vec<OrgRepository>> {
+        self.list_pages(
+            &format!(
+                "/orgs/{}/teams/{}/repos",
+                org,
+                encode_path_segment(team_slug)
+            ),
+            |list: Vec<_>| list,
+        )
+        .await
+    }
+
+    /// Fetch every page of a listing endpoint
+    ///
+    /// `items_of` takes the entries out of one page, which is either a plain
+    /// JSON array or an object wrapping one.
+    async fn list_pages<P, T>(&self, path: &str, items_of: impl Fn(P) -> Vec<T>) -> Result<Vec<T>>
+    where
+        P: serde::de::DeserializeOwned,
+    {
+        let mut items = Vec::new();
+        let mut page = 1;
+        loop {
+            let url = format!("{}?per_page={}&page={}", path, PER_PAGE, page);
+            let resp = self.send(self.request(reqwest::Method::GET, &url)).await?;
+            let list = items_of(resp.json().await?);
+            let count = list.len();
+            items.extend(list);
+            if count < PER_PAGE {
+                return Ok(items);
+            }
+            page += 1;
+        }
+    }
+
+    /// Get a repository, mainly to resolve its numeric ID
+    pub async fn get_repository(&self, org: &str, repo: &str) -> Result<Repository> {
+        let path = format!("/repos/{}/{}", org, repo);
+        let resp = self.send(self.request(reqwest::Method::GET, &path)).await?;
+        let repository: Repository = resp.json().await?;
+        Ok(repository)
+    }
+
+    /// Check whether a deployment environment exists in a repository
+    pub async fn environment_exists(
+        &self,
+        org: &str,
+        repo: &str,
+        environment: &str,
+    ) -> Result<bool> {
+        let path = format!(
+            "/repos/{}/{}/environments/{}",
+            org,
+            repo,
+            encode_path_segment(environment)
+        );
         let resp = self
-            .client
-            .put(&url)
-            .header("Authorization", format!("token {}", self.token))
-            .header("User-Agent", "gsm-cli")
-            .json(&body)
-            .send()
+            .send_optional(self.request(reqwest::Method::GET, &path))
+            .await?;
+        Ok(resp.is_some())
+    }
+
+    /// Create a deployment environment with default protection settings
+    pub async fn create_environment(&self, org: &str, repo: &str, environment: &str) -> Result<()> {
+        let path = format!(
+            "/repos/{}/{}/environments/{}",
+            org,
+            repo,
+            encode_path_segment(environment)
+        );
+        self.send(self.request(reqwest::Method::PUT, &path)).await?;
+        Ok(())
+    }
+
+    /// Get the public key for a repository environment
+    pub async fn get_environment_public_key(
+        &self,
+        org: &str,
+        repo: &str,
+        environment: &str,
+    ) -> Result<PublicKey> {
+        let path = format!(
+            "/repos/{}/{}/environments/{}/secrets/public-key",
+            org,
+            repo,
+            encode_path_segment(environment)
+        );
+        let resp = self.send(self.request(reqwest::Method::GET, &path)).await?;
+        let public_key: PublicKey = resp.json().await?;
+        Ok(public_key)
+    }
+
+    /// Push a secret to a repository environment
+    pub async fn push_environment_secret(
+        &self,
+        org: &str,
+        repo: &str,
+        environment: &str,
+        secret_name: &str,
+        encrypted_value: &str,
+        key_id: &str,
+    ) -> Result<()> {
+        let path = format!(
+            "/repos/{}/{}/environments/{}/secrets/{}",
+            org,
+            repo,
+            encode_path_segment(environment),
+            secret_name
+        );
+        let body = SecretBody {
+            encrypted_value,
+            key_id,
+        };
+        self.send(self.request(reqwest::Method::PUT, &path).json(&body))
+            .await?;
+        Ok(())
+    }
+
+    /// List all secrets in a repository environment, following pagination
+    pub async fn list_environment_secrets(
+        &self,
+        org: &str,
+        repo: &str,
+        environment: &str,
+    ) -> Result<Vec<SecretInfo>> {
+        let path = format!(
+            "/repos/{}/{}/environments/{}/secrets",
+            org,
+            repo,
+            encode_path_segment(environment)
+        );
+        self.list_secrets(&path).await
+    }
+
+    /// Delete a secret from a repository environment
+    pub async fn delete_environment_secret(
+        &self,
+        org: &str,
+        repo: &str,
+        environment: &str,
+        secret_name: &str,
+    ) -> Result<()> {
+        let path = format!(
+            "/repos/{}/{}/environments/{}/secrets/{}",
+            org,
+            repo,
+            encode_path_segment(environment),
+            secret_name
+        );
+        self.send(self.request(reqwest::Method::DELETE, &path))
+            .await?;
+        Ok(())
+    }
+
+    /// List all secrets in an organization secret store, following pagination
+    pub async fn list_org_secrets(
+        &self,
+        target: SecretTarget,
+        org: &str,
+    ) -> Result<Vec<SecretInfo>> {
+        let path = format!("/orgs/{}/{}/secrets", org, target.api_segment());
+        self.list_secrets(&path).await
+    }
+
+    /// Get the public key of an organization secret store
+    pub async fn get_org_public_key(&self, target: SecretTarget, org: &str) -> Result<PublicKey> {
+        let path = format!("/orgs/{}/{}/secrets/public-key", org, target.api_segment());
+        let resp = self.send(self.request(reqwest::Method::GET, &path)).await?;
+        let public_key: PublicKey = resp.json().await?;
+        Ok(public_key)
+    }
+
+    /// Push a secret to an organization secret store
+    pub async fn push_org_secret(
+        &self,
+        target: SecretTarget,
+        org: &str,
+        secret_name: &str,
+        body: &OrgSecretBody<'_>,
+    ) -> Result<()> {
+        let path = format!(
+            "/orgs/{}/{}/secrets/{}",
+            org,
+            target.api_segment(),
+            secret_name
+        );
+        self.send(self.request(reqwest::Method::PUT, &path).json(body))
             .await?;
+        Ok(())
+    }
 
-        if resp.status().is_success() {
-            Ok(())
-        } else {
-            Err(GithubError::HttpError(resp.text().await?))
+    /// Get an Actions variable, or `None` if it does not exist
+    pub async fn get_variable(
+        &self,
+        scope: VariableScope<'_>,
+        name: &str,
+    ) -> Result<Option<Variable>> {
+        let path = format!("{}/{}", scope.path(), name);
+        match self
+            .send_optional(self.request(reqwest::Method::GET, &path))
+            .await?
+        {
+            Some(resp) => Ok(Some(resp.json().await?)),
+            None => Ok(None),
         }
     }
+
+    /// Create an Actions variable
+    pub async fn create_variable(
+        &self,
+        scope: VariableScope<'_>,
+        body: &VariableBody<'_>,
+    ) -> Result<()> {
+        let path = scope.path();
+        self.send(self.request(reqwest::Method::POST, &path).json(body))
+            .await?;
+        Ok(())
+    }
+
+    /// Update an existing Actions variable
+    pub async fn update_variable(
+        &self,
+        scope: VariableScope<'_>,
+        body: &VariableBody<'_>,
+    ) -> Result<()> {
+        let path = format!("{}/{}", scope.path(), body.name);
+        self.send(self.request(reqwest::Method::PATCH, &path).json(body))
+            .await?;
+        Ok(())
+    }
+
+    /// List the IDs of repositories selected for an organization variable
+    pub async fn get_org_variable_repository_ids(&self, org: &str, name: &str) -> Result<Vec<u64>> {
+        let path = format!("/orgs/{}/actions/variables/{}/repositories", org, name);
+        let repositories = self
+            .list_pages(&path, |list: RepositoryList| list.repositories)
+            .await?;
+        Ok(repositories.into_iter().map(|r| r.id).collect())
+    }
+}
+
+#[derive(Serialize)]
+pub struct VariableBody<'a> {
+    pub name: &'a str,
+    pub value: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub visibility: Option<Visibility>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub selected_repository_ids: Option<&'a [u64]>,
 }
 
 #[derive(Serialize)]
@@ -107,6 +757,38 @@ pub struct SecretBody<'a> {
     pub key_id: &'a str,
 }
 
+#[derive(Serialize)]
+pub struct OrgSecretBody<'a> {
+    pub encrypted_value: &'a str,
+    pub key_id: &'a str,
+    pub visibility: Visibility,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub selected_repository_ids: Option<&'a [u64]>,
+}
+
+/// Turn an unsuccessful response into an error carrying its status and body
+async fn http_error(resp: reqwest::Response) -> GithubError {
+    let status = resp.status();
+    match resp.text().await {
+        Ok(body) => GithubError::HttpError(format!("{}: {}", status, body)),
+        Err(e) => e.into(),
+    }
+}
+
+/// Percent-encode a path segment such as an environment name
+fn encode_path_segment(segment: &str) -> String {
+    let mut encoded = String::with_capacity(segment.len());
+    for byte in segment.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
 pub fn encrypt_github_secret(public_key_b64: &str, secret: &str) -> Result<String> {
     use sodiumoxide::crypto::box_::PublicKey as SodiumPublicKey;
     use sodiumoxide::crypto::sealedbox;
@@ -5,3 +5,4 @@ pub mod config;
 pub mod crypto;
 pub mod error;
 pub mod github;
+pub mod plan;
@@ -1,15 +1,11 @@
-use crate::error::Result;
 use clap::Parser;
 use colored::Colorize;
-
-mod cli;
-mod config;
-mod crypto;
-mod error;
-mod github;
+use gsm::cli;
+use gsm::error::Result;
+use std::process::ExitCode;
 
 #[tokio::main]
-async fn main() -> Result<()> {
+async fn main() -> Result<ExitCode> {
     // Load environment variables from .env file if present
     if let Err(e) = dotenvy::dotenv() {
         eprintln!("{}: Could not load .env file: {}", "Warning".yellow(), e);
@@ -17,12 +13,22 @@ async fn main() -> Result<()> {
 
     let cli = cli::Cli::parse();
     match &cli.command {
-        cli::Commands::Validate(args) => cli::validate::run(args)?,
+        cli::Commands::Validate(args) => cli::validate::run(args).await?,
         cli::Commands::Encrypt(args) => cli::encrypt::run(args)?,
         cli::Commands::Decrypt(args) => cli::decrypt::run(args)?,
         cli::Commands::EncryptAll(args) => cli::encrypt_all::run(args)?,
         cli::Commands::DecryptAll(args) => cli::decrypt_all::run(args)?,
-        cli::Commands::Push(args) => cli::push::run(args).await?,
+        cli::Commands::Edit(args) => cli::edit::run(args)?,
+        cli::Commands::Get(args) => cli::get::run(args)?,
+        cli::Commands::Set(args) => cli::set::run(args)?,
+        cli::Commands::Unset(args) => cli::unset::run(args)?,
+        cli::Commands::Migrate(args) => cli::migrate::run(args)?,
+        cli::Commands::Keygen(args) => cli::keygen::run(args)?,
+        cli::Commands::Rekey(args) => cli::rekey::run(args)?,
+        cli::Commands::Push(args) => return cli::push::run(args).await,
+        cli::Commands::Sync(args) => cli::sync::run(args).await?,
+        cli::Commands::Plan(args) => cli::plan::run(args).await?,
+        cli::Commands::Apply(args) => cli::apply::run(args).await?,
     }
-    Ok(())
+    Ok(ExitCode::SUCCESS)
 }
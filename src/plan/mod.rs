@@ -0,0 +1,770 @@
+// Plan/apply engine: diff a config against remote secrets and execute the result
+
+use crate::config::{self, Config, SecretTarget, Visibility};
+use crate::crypto::{self, Key};
+use crate::error::Result;
+use crate::github::{GithubClient, OrgSecretBody, PublicKey, SecretInfo, encrypt_github_secret};
+use base64::{Engine as _, engine::general_purpose};
+use colored::Colorize;
+use futures::future::try_join_all;
+use glob::Pattern;
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt::{self, Write as _};
+use std::fs;
+use std::io::Write as _;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+const SALT_LEN: usize = 16;
+
+#[derive(Debug, Error)]
+pub enum PlanError {
+    #[error("Plan file error: {0}")]
+    FileError(#[from] std::io::Error),
+    #[error("Plan JSON error: {0}")]
+    JsonError(#[from] serde_json::Error),
+    #[error("Plan is out of date: {0}")]
+    Stale(String),
+    #[error("Invalid plan: {0}")]
+    Invalid(String),
+}
+
+/// Fingerprints of values gsm has pushed, used to detect changes without
+/// reading secrets back from GitHub
+///
+/// Fingerprints are keyed with a random key kept next to the state file in
+/// [`State::key_path`], readable only by its owner, so the state file alone
+/// is not enough to test guesses of a secret value.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct State {
+    pub salt: String,
+    #[serde(default)]
+    pub secrets: BTreeMap<String, StateEntry>,
+    #[serde(skip)]
+    key: Key,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StateEntry {
+    pub fingerprint: String,
+    pub updated_at: String,
+}
+
+impl State {
+    /// Load the state file, or start a fresh one with a new salt, along with
+    /// its fingerprint key, which is created on first use
+    pub fn load_or_init(path: &Path) -> std::result::Result<State, PlanError> {
+        let mut state = if path.exists() {
+            serde_json::from_str(&fs::read_to_string(path)?)?
+        } else {
+            let mut salt = [0u8; SALT_LEN];
+            rand::rng().fill_bytes(&mut salt);
+            State {
+                salt: general_purpose::STANDARD.encode(salt),
+                secrets: BTreeMap::new(),
+                key: Key::default(),
+            }
+        };
+        state.key = load_or_create_key(&Self::key_path(path))?;
+        Ok(state)
+    }
+
+    /// Where the fingerprint key of the state file at `path` is kept
+    pub fn key_path(path: &Path) -> PathBuf {
+        let mut name = path.as_os_str().to_owned();
+        name.push(".key");
+        PathBuf::from(name)
+    }
+
+    pub fn save(&self, path: &Path) -> std::result::Result<(), PlanError> {
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// Keyed fingerprint of everything that determines a pushed secret
+    pub fn fingerprint(&self, key: &str, material: &str) -> String {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(&self.key).expect("HMAC accepts keys of any length");
+        mac.update(key.as_bytes());
+        mac.update(&[0]);
+        mac.update(material.as_bytes());
+        let mut hex = String::new();
+        for byte in mac.finalize().into_bytes() {
+            let _ = write!(hex, "{:02x}", byte);
+        }
+        hex
+    }
+}
+
+/// Read a state fingerprint key, or create one only its owner can read
+fn load_or_create_key(path: &Path) -> std::result::Result<Key, PlanError> {
+    if path.exists() {
+        let encoded = fs::read_to_string(path)?;
+        return general_purpose::STANDARD
+            .decode(encoded.trim())
+            .ok()
+            .and_then(|key| Key::try_from(key).ok())
+            .ok_or_else(|| PlanError::Invalid(format!("'{}' is not a state key", path.display())));
+    }
+    let key = crypto::generate_key();
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    writeln!(
+        options.open(path)?,
+        "{}",
+        general_purpose::STANDARD.encode(key)
+    )?;
+    Ok(key)
+}
+
+/// A secret store holding a set of secrets
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(tag = "scope", rename_all = "snake_case")]
+pub enum SecretLocation {
+    Repo { repo: String, target: SecretTarget },
+    Environment { repo: String, environment: String },
+    Org { target: SecretTarget },
+}
+
+impl SecretLocation {
+    /// Repository the location belongs to, or `None` for the organization
+    fn repo(&self) -> Option<&str> {
+        match self {
+            SecretLocation::Repo { repo, .. } | SecretLocation::Environment { repo, .. } => {
+                Some(repo)
+            }
+            SecretLocation::Org { .. } => None,
+        }
+    }
+
+    fn state_key(&self, org: &str, name: &str) -> String {
+        let name = name.to_uppercase();
+        match self {
+            SecretLocation::Repo { repo, target } => {
+                format!("{}/{}/{}/{}", org, repo, target, name)
+            }
+            SecretLocation::Environment { repo, environment } => {
+                format!("{}/{}/environments/{}/{}", org, repo, environment, name)
+            }
+            SecretLocation::Org { target } => format!("{}/{}/{}", org, target, name),
+        }
+    }
+
+    fn label(&self, name: &str) -> String {
+        match self {
+            SecretLocation::Repo { target, .. } | SecretLocation::Org { target } => {
+                secret_label(name, *target)
+            }
+            SecretLocation::Environment { environment, .. } => {
+                format!("{} (environment {})", name, environment)
+            }
+        }
+    }
+
+    /// Fetch the secrets currently stored at this location
+    async fn list(&self, github_client: &GithubClient, org: &str) -> Result<Vec<SecretInfo>> {
+        let secrets = match self {
+            SecretLocation::Repo { repo, target } => {
+                github_client.list_repo_secrets(*target, org, repo).await?
+            }
+            SecretLocation::Environment { repo, environment } => {
+                if github_client
+                    .environment_exists(org, repo, environment)
+                    .await?
+                {
+                    github_client
+                        .list_environment_secrets(org, repo, environment)
+                        .await?
+                } else {
+                    Vec::new()
+                }
+            }
+            SecretLocation::Org { target } => github_client.list_org_secrets(*target, org).await?,
+        };
+        Ok(secrets)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    Create,
+    Update,
+    Delete,
+    Unchanged,
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Action::Create => "create",
+            Action::Update => "update",
+            Action::Delete => "delete",
+            Action::Unchanged => "unchanged",
+        };
+        f.write_str(s)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Change {
+    pub location: SecretLocation,
+    pub name: String,
+    pub action: Action,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    /// Fingerprint of the planned value, checked again before applying
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fingerprint: Option<String>,
+}
+
+/// The changes needed to bring GitHub in line with a config; never holds secret values
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Plan {
+    pub config: PathBuf,
+    pub org: String,
+    pub salt: String,
+    pub changes: Vec<Change>,
+}
+
+impl Plan {
+    pub fn load(path: &Path) -> std::result::Result<Plan, PlanError> {
+        let content = fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&content)?)
+    }
+
+    pub fn save(&self, path: &Path) -> std::result::Result<(), PlanError> {
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    fn count(&self, action: Action) -> usize {
+        self.changes.iter().filter(|c| c.action == action).count()
+    }
+
+    pub fn has_changes(&self) -> bool {
+        self.changes.iter().any(|c| c.action != Action::Unchanged)
+    }
+
+    /// Print the plan grouped by repository
+    pub fn print(&self) {
+        let mut heading = None;
+        for change in &self.changes {
+            let group = change.location.repo();
+            if heading != Some(group) {
+                match group {
+                    Some(repo) => println!("Repo: {}", repo),
+                    None => println!("Org: {}", self.org),
+                }
+                heading = Some(group);
+            }
+            let label = change.location.label(&change.name);
+            let detail = match &change.reason {
+                Some(reason) => format!("{}: {}", change.action, reason),
+                None => change.action.to_string(),
+            };
+            let line = match change.action {
+                Action::Create => format!("  + {} ({})", label, detail).green(),
+                Action::Update => format!("  ~ {} ({})", label, detail).yellow(),
+                Action::Delete => format!("  - {} ({})", label, detail).red(),
+                Action::Unchanged => format!("    {} ({})", label, detail).normal(),
+            };
+            println!("{}", line);
+        }
+        println!(
+            "Plan: {} to create, {} to update, {} to delete, {} unchanged",
+            self.count(Action::Create),
+            self.count(Action::Update),
+            self.count(Action::Delete),
+            self.count(Action::Unchanged)
+        );
+    }
+}
+
+/// A secret the config wants to exist
+struct Desired<'a> {
+    location: SecretLocation,
+    name: &'a str,
+    value: &'a str,
+    /// Everything the fingerprint covers: the value plus org access settings
+    material: String,
+    visibility: Visibility,
+    selected_repositories: &'a [String],
+}
+
+fn desired_secrets(config: &Config) -> Vec<Desired<'_>> {
+    let mut desired = Vec::new();
+    for repo in &config.repositories {
+        for (name, value) in config.repo_secrets(repo) {
+            for &target in config.secret_targets(name) {
+                desired.push(Desired {
+                    location: SecretLocation::Repo {
+                        repo: repo.clone(),
+                        target,
+                    },
+                    name,
+                    value,
+                    material: value.to_string(),
+                    visibility: Visibility::default(),
+                    selected_repositories: &[],
+                });
+            }
+        }
+        for (environment, env) in &config.environments {
+            for (name, value) in &env.env {
+                desired.push(Desired {
+                    location: SecretLocation::Environment {
+                        repo: repo.clone(),
+                        environment: environment.clone(),
+                    },
+                    name,
+                    value,
+                    material: value.clone(),
+                    visibility: Visibility::default(),
+                    selected_repositories: &[],
+                });
+            }
+        }
+    }
+    for (name, secret) in &config.org_secrets {
+        for &target in &secret.targets {
+            desired.push(Desired {
+                location: SecretLocation::Org { target },
+                name,
+                value: &secret.value,
+                material: format!(
+                    "{}\0{}\0{}",
+                    secret.value,
+                    secret.visibility,
+                    secret.selected_repositories.join(",")
+                ),
+                visibility: secret.visibility,
+                selected_repositories: &secret.selected_repositories,
+            });
+        }
+    }
+    desired
+}
+
+/// Every location the plan inspects; repository stores and environments are
+/// included even when empty so undeclared secrets there can be pruned
+fn managed_locations(config: &Config) -> Vec<SecretLocation> {
+    let mut locations = Vec::new();
+    for repo in &config.repositories {
+        for target in config.managed_targets() {
+            locations.push(SecretLocation::Repo {
+                repo: repo.clone(),
+                target,
+            });
+        }
+        for environment in config.environments.keys() {
+            locations.push(SecretLocation::Environment {
+                repo: repo.clone(),
+                environment: environment.clone(),
+            });
+        }
+    }
+    let mut org_targets: Vec<SecretTarget> = config
+        .org_secrets
+        .values()
+        .flat_map(|secret| secret.targets.iter().copied())
+        .collect();
+    org_targets.sort();
+    org_targets.dedup();
+    locations.extend(
+        org_targets
+            .into_iter()
+            .map(|target| SecretLocation::Org { target }),
+    );
+    locations
+}
+
+/// Compare a config with the secrets present on GitHub
+///
+/// Organization secrets are never planned for deletion.
+pub async fn build_plan(
+    github_client: &GithubClient,
+    config: &Config,
+    config_path: &Path,
+    state: &State,
+    prune: bool,
+    protected: &[Pattern],
+) -> Result<Plan> {
+    let desired = desired_secrets(config);
+    let mut changes = Vec::new();
+
+    for location in managed_locations(config) {
+        let remote: HashMap<String, SecretInfo> = location
+            .list(github_client, &config.org)
+            .await?
+            .into_iter()
+            .map(|secret| (secret.name.to_uppercase(), secret))
+            .collect();
+
+        let mut declared = Vec::new();
+        for wanted in desired.iter().filter(|d| d.location == location) {
+            let key = location.state_key(&config.org, wanted.name);
+            let fingerprint = state.fingerprint(&key, &wanted.material);
+            let remote_secret = remote.get(&wanted.name.to_uppercase());
+            let (action, reason) = match (remote_secret, state.secrets.get(&key)) {
+                (None, _) => (Action::Create, None),
+                (Some(_), None) => (Action::Update, Some("no recorded fingerprint")),
+                (Some(_), Some(entry)) if entry.fingerprint != fingerprint => {
+                    (Action::Update, Some("value changed"))
+                }
+                (Some(secret), Some(entry)) if entry.updated_at != secret.updated_at => {
+                    (Action::Update, Some("changed outside gsm"))
+                }
+                (Some(_), Some(_)) => (Action::Unchanged, None),
+            };
+            declared.push(wanted.name.to_uppercase());
+            changes.push(Change {
+                location: location.clone(),
+                name: wanted.name.to_string(),
+                action,
+                reason: reason.map(str::to_string),
+                fingerprint: Some(fingerprint),
+            });
+        }
+
+        if prune && location.repo().is_some() {
+            for (name, secret) in &remote {
+                if declared.contains(name) {
+                    continue;
+                }
+                let (action, reason) = if config::is_protected(protected, &secret.name) {
+                    (Action::Unchanged, Some("protected"))
+                } else {
+                    (Action::Delete, Some("not in config"))
+                };
+                changes.push(Change {
+                    location: location.clone(),
+                    name: secret.name.clone(),
+                    action,
+                    reason: reason.map(str::to_string),
+                    fingerprint: None,
+                });
+            }
+        }
+    }
+
+    changes.sort_by(|a, b| {
+        let group = |c: &Change| {
+            (
+                c.location.repo().is_none(),
+                c.location.repo().map(str::to_string),
+            )
+        };
+        (group(a), &a.location, &a.name).cmp(&(group(b), &b.location, &b.name))
+    });
+
+    Ok(Plan {
+        config: config_path.to_path_buf(),
+        org: config.org.clone(),
+        salt: state.salt.clone(),
+        changes,
+    })
+}
+
+/// Execute a plan, recording fingerprints of pushed values in the state file
+///
+/// Every planned change is checked against the config before anything is
+/// pushed: a value that differs from the planned one, or a planned deletion
+/// of a secret the config now declares or `protected` now matches, means the
+/// plan was made from a different version of the config and is rejected.
+pub async fn apply_plan(
+    github_client: &GithubClient,
+    config: &Config,
+    plan: &Plan,
+    state: &mut State,
+    state_path: &Path,
+    protected: &[Pattern],
+) -> Result<()> {
+    if plan.org != config.org {
+        return Err(PlanError::Stale(format!(
+            "plan targets org '{}' but the config targets '{}'",
+            plan.org, config.org
+        ))
+        .into());
+    }
+    if plan.salt != state.salt {
+        return Err(PlanError::Stale("state file was replaced after planning".to_string()).into());
+    }
+
+    let desired = desired_secrets(config);
+    // Keep the plan's order so output stays grouped by repository
+    let mut by_location: Vec<(&SecretLocation, LocationChanges)> = Vec::new();
+    for change in &plan.changes {
+        let index = match by_location.iter().position(|(l, _)| *l == &change.location) {
+            Some(index) => index,
+            None => {
+                by_location.push((&change.location, LocationChanges::default()));
+                by_location.len() - 1
+            }
+        };
+        let changes = &mut by_location[index].1;
+        match change.action {
+            Action::Create | Action::Update => {
+                let wanted = desired
+                    .iter()
+                    .find(|d| d.location == change.location && d.name == change.name)
+                    .filter(|d| {
+                        let key = d.location.state_key(&config.org, d.name);
+                        Some(state.fingerprint(&key, &d.material)) == change.fingerprint
+                    })
+                    .ok_or_else(|| {
+                        PlanError::Stale(format!(
+                            "{} changed in the config since the plan was made",
+                            change.location.label(&change.name)
+                        ))
+                    })?;
+                changes.pushes.push((change, wanted));
+            }
+            Action::Delete if change.location.repo().is_none() => {
+                return Err(PlanError::Invalid(format!(
+                    "{} is an organization secret, which plans never delete",
+                    change.name
+                ))
+                .into());
+            }
+            Action::Delete => {
+                let declared = desired.iter().any(|d| {
+                    d.location == change.location && d.name.eq_ignore_ascii_case(&change.name)
+                });
+                if declared || config::is_protected(protected, &change.name) {
+                    return Err(PlanError::Stale(format!(
+                        "{} was planned for deletion but the config now declares or protects it",
+                        change.location.label(&change.name)
+                    ))
+                    .into());
+                }
+                changes.deletes.push(change)
+            }
+            Action::Unchanged => {}
+        }
+    }
+
+    let selected = by_location
+        .iter()
+        .flat_map(|(_, changes)| &changes.pushes)
+        .flat_map(|(_, wanted)| wanted.selected_repositories);
+    let repo_ids = resolve_repository_ids(github_client, &config.org, selected).await?;
+    let mut heading = None;
+    for (location, changes) in by_location {
+        if changes.pushes.is_empty() && changes.deletes.is_empty() {
+            continue;
+        }
+        if heading != Some(location.repo()) {
+            match location.repo() {
+                Some(repo) => println!("Applying changes to repo: {}...", repo),
+                None => println!("Applying changes to org: {}...", config.org),
+            }
+            heading = Some(location.repo());
+        }
+
+        if !changes.pushes.is_empty() {
+            if let SecretLocation::Environment { repo, environment } = location
+                && !github_client
+                    .environment_exists(&config.org, repo, environment)
+                    .await?
+            {
+                github_client
+                    .create_environment(&config.org, repo, environment)
+                    .await?;
+                println!("  (created environment {})", environment);
+            }
+            let public_key = get_public_key(github_client, &config.org, location).await?;
+            for (change, wanted) in &changes.pushes {
+                let encrypted = encrypt_github_secret(&public_key.key, wanted.value)?;
+                match location {
+                    SecretLocation::Repo { repo, target } => {
+                        github_client
+                            .push_repo_secret(
+                                *target,
+                                &config.org,
+                                repo,
+                                &change.name,
+                                &encrypted,
+                                &public_key.key_id,
+                            )
+                            .await?
+                    }
+                    SecretLocation::Environment { repo, environment } => {
+                        github_client
+                            .push_environment_secret(
+                                &config.org,
+                                repo,
+                                environment,
+                                &change.name,
+                                &encrypted,
+                                &public_key.key_id,
+                            )
+                            .await?
+                    }
+                    SecretLocation::Org { target } => {
+                        let selected_ids = selected_ids(
+                            wanted.visibility,
+                            wanted.selected_repositories,
+                            &repo_ids,
+                        );
+                        let body = OrgSecretBody {
+                            encrypted_value: &encrypted,
+                            key_id: &public_key.key_id,
+                            visibility: wanted.visibility,
+                            selected_repository_ids: (wanted.visibility == Visibility::Selected)
+                                .then_some(selected_ids.as_slice()),
+                        };
+                        github_client
+                            .push_org_secret(*target, &config.org, &change.name, &body)
+                            .await?
+                    }
+                }
+                let verb = match change.action {
+                    Action::Create => "created",
+                    _ => "updated",
+                };
+                println!("  - {}: {}", location.label(&change.name), verb);
+            }
+        }
+
+        for change in &changes.deletes {
+            delete_secret(github_client, &config.org, location, &change.name).await?;
+            state
+                .secrets
+                .remove(&location.state_key(&config.org, &change.name));
+            println!("  - {}: deleted", location.label(&change.name));
+        }
+
+        // Record the new timestamps so the next plan sees these as unchanged
+        if !changes.pushes.is_empty() {
+            let remote = location.list(github_client, &config.org).await?;
+            for (change, _) in &changes.pushes {
+                let Some(fingerprint) = &change.fingerprint else {
+                    continue;
+                };
+                if let Some(secret) = remote
+                    .iter()
+                    .find(|s| s.name.eq_ignore_ascii_case(&change.name))
+                {
+                    state.secrets.insert(
+                        location.state_key(&config.org, &change.name),
+                        StateEntry {
+                            fingerprint: fingerprint.clone(),
+                            updated_at: secret.updated_at.clone(),
+                        },
+                    );
+                }
+            }
+        }
+        state.save(state_path)?;
+    }
+    Ok(())
+}
+
+/// Planned work for one location, with the config entry behind each push
+#[derive(Default)]
+struct LocationChanges<'a> {
+    pushes: Vec<(&'a Change, &'a Desired<'a>)>,
+    deletes: Vec<&'a Change>,
+}
+
+async fn get_public_key(
+    github_client: &GithubClient,
+    org: &str,
+    location: &SecretLocation,
+) -> Result<PublicKey> {
+    let key = match location {
+        SecretLocation::Repo { repo, target } => {
+            github_client
+                .get_repo_public_key(*target, org, repo)
+                .await?
+        }
+        SecretLocation::Environment { repo, environment } => {
+            github_client
+                .get_environment_public_key(org, repo, environment)
+                .await?
+        }
+        SecretLocation::Org { target } => github_client.get_org_public_key(*target, org).await?,
+    };
+    Ok(key)
+}
+
+async fn delete_secret(
+    github_client: &GithubClient,
+    org: &str,
+    location: &SecretLocation,
+    name: &str,
+) -> Result<()> {
+    match location {
+        SecretLocation::Repo { repo, target } => {
+            github_client
+                .delete_repo_secret(*target, org, repo, name)
+                .await?
+        }
+        SecretLocation::Environment { repo, environment } => {
+            github_client
+                .delete_environment_secret(org, repo, environment, name)
+                .await?
+        }
+        SecretLocation::Org { .. } => {
+            return Err(PlanError::Invalid(format!(
+                "{} is an organization secret, which plans never delete",
+                name
+            ))
+            .into());
+        }
+    }
+    Ok(())
+}
+
+/// Name of a secret as printed in push and plan output, qualified by its store
+pub fn secret_label(secret_name: &str, target: SecretTarget) -> String {
+    match target {
+        SecretTarget::Actions => secret_name.to_string(),
+        _ => format!("{} ({})", secret_name, target),
+    }
+}
+
+/// Look up the numeric IDs of repositories, concurrently and once per name
+pub async fn resolve_repository_ids<'a>(
+    github_client: &GithubClient,
+    org: &str,
+    repos: impl IntoIterator<Item = &'a String>,
+) -> Result<HashMap<String, u64>> {
+    let repos: BTreeSet<&String> = repos.into_iter().collect();
+    let ids = try_join_all(
+        repos
+            .iter()
+            .map(|repo| async move { github_client.get_repository(org, repo).await }),
+    )
+    .await?;
+    Ok(repos
+        .into_iter()
+        .zip(ids)
+        .map(|(repo, repository)| (repo.clone(), repository.id))
+        .collect())
+}
+
+/// IDs of the selected repositories of an org entry, empty unless `selected`
+pub fn selected_ids(
+    visibility: Visibility,
+    selected_repositories: &[String],
+    repo_ids: &HashMap<String, u64>,
+) -> Vec<u64> {
+    if visibility != Visibility::Selected {
+        return Vec::new();
+    }
+    selected_repositories
+        .iter()
+        .filter_map(|repo| repo_ids.get(repo).copied())
+        .collect()
+}
@@ -0,0 +1,142 @@
+#![allow(dead_code)]
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+
+/// A request received by the stub server
+#[derive(Debug, Clone)]
+pub struct Request {
+    pub method: String,
+    /// Path including the query string
+    pub path: String,
+    /// Header names are lower-cased
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+impl Request {
+    /// Value of the header `name`, given in lower case
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// A response from the stub server
+pub struct Reply {
+    pub status: u16,
+    pub headers: Vec<(&'static str, String)>,
+    pub body: String,
+}
+
+impl From<(u16, String)> for Reply {
+    fn from((status, body): (u16, String)) -> Self {
+        Reply {
+            status,
+            headers: Vec::new(),
+            body,
+        }
+    }
+}
+
+/// A minimal HTTP server standing in for the GitHub API: every request is
+/// recorded and answered by `handler`, usually with a status code and JSON body
+pub struct Stub {
+    pub url: String,
+    requests: Arc<Mutex<Vec<Request>>>,
+}
+
+impl Stub {
+    pub fn start<F, R>(handler: F) -> Self
+    where
+        F: Fn(&Request) -> R + Send + Sync + 'static,
+        R: Into<Reply>,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bind");
+        let url = format!("http://{}", listener.local_addr().expect("address"));
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let recorded = Arc::clone(&requests);
+        let handler = Arc::new(handler);
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let stream = stream.expect("connection");
+                let handler = Arc::clone(&handler);
+                let recorded = Arc::clone(&recorded);
+                // One thread per connection, so concurrent requests are in
+                // flight together as they would be against GitHub
+                std::thread::spawn(move || serve(stream, &*handler, &recorded));
+            }
+        });
+        Stub { url, requests }
+    }
+
+    /// Requests received so far, in order
+    pub fn requests(&self) -> Vec<Request> {
+        self.requests.lock().expect("lock").clone()
+    }
+
+    /// Received requests with the given method, as `METHOD path` strings
+    pub fn calls(&self, method: &str) -> Vec<String> {
+        self.requests()
+            .into_iter()
+            .filter(|request| request.method == method)
+            .map(|request| format!("{} {}", request.method, request.path))
+            .collect()
+    }
+}
+
+/// Read one request from `stream`, record it and write `handler`'s reply
+fn serve<R: Into<Reply>>(
+    mut stream: TcpStream,
+    handler: &dyn Fn(&Request) -> R,
+    recorded: &Mutex<Vec<Request>>,
+) {
+    let mut reader = BufReader::new(stream.try_clone().expect("clone"));
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line).expect("request line");
+    let mut headers = Vec::new();
+    loop {
+        let mut header = String::new();
+        reader.read_line(&mut header).expect("header");
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(": ") {
+            headers.push((name.to_lowercase(), value.to_string()));
+        }
+    }
+    let length = headers
+        .iter()
+        .find(|(name, _)| name == "content-length")
+        .map_or(0, |(_, value)| value.parse().expect("length"));
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).expect("body");
+
+    let mut parts = request_line.split(' ');
+    let request = Request {
+        method: parts.next().unwrap_or_default().to_string(),
+        path: parts.next().unwrap_or_default().to_string(),
+        headers,
+        body: String::from_utf8_lossy(&body).into_owned(),
+    };
+    let reply = handler(&request).into();
+    recorded.lock().expect("lock").push(request);
+    let headers: String = reply
+        .headers
+        .iter()
+        .map(|(name, value)| format!("{}: {}\r\n", name, value))
+        .collect();
+    write!(
+        stream,
+        "HTTP/1.1 {} Stub\r\nContent-Type: application/json\r\nContent-Length: {}\r\n{}Connection: close\r\n\r\n{}",
+        reply.status,
+        reply.body.len(),
+        headers,
+        reply.body
+    )
+    .expect("response");
+}
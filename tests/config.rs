@@ -2,7 +2,7 @@ use std::fs::File;
 use std::io::Write;
 use tempfile::tempdir;
 
-use gsm::config::{self, Config, ConfigError};
+use gsm::config::{self, Config, ConfigError, SecretTarget, Visibility};
 
 #[test]
 fn parse_config_file() {
@@ -45,3 +45,297 @@ fn invalid_yaml_returns_error() {
     let err = config::load_config_from_file(&path).unwrap_err();
     matches!(err, ConfigError::YamlParseError(_));
 }
+
+#[test]
+fn parse_org_secrets() {
+    let dir = tempdir().expect("tempdir");
+    let path = dir.path().join("org.yaml");
+    let yaml = r#"
+org: example
+repositories: []
+env: {}
+org_secrets:
+  SHARED_TOKEN:
+    value: token
+    visibility: selected
+    selected_repositories:
+      - repo1
+  NPM_TOKEN:
+    value: npm
+"#;
+    std::fs::write(&path, yaml).expect("write");
+
+    let config = config::load_config_from_file(&path).expect("load");
+    let shared = &config.org_secrets["SHARED_TOKEN"];
+    assert_eq!(shared.visibility, Visibility::Selected);
+    assert_eq!(shared.selected_repositories, vec!["repo1"]);
+    assert_eq!(
+        config.org_secrets["NPM_TOKEN"].visibility,
+        Visibility::Private
+    );
+}
+
+#[test]
+fn selected_visibility_requires_repositories() {
+    let dir = tempdir().expect("tempdir");
+    let path = dir.path().join("org.yaml");
+    let yaml = r#"
+org: example
+repositories: []
+env: {}
+org_secrets:
+  SHARED_TOKEN:
+    value: token
+    visibility: selected
+"#;
+    std::fs::write(&path, yaml).expect("write");
+
+    let err = config::load_config_from_file(&path).unwrap_err();
+    assert!(matches!(err, ConfigError::Invalid(_)));
+}
+
+#[test]
+fn parse_environments() {
+    let dir = tempdir().expect("tempdir");
+    let path = dir.path().join("envs.yaml");
+    let yaml = r#"
+org: example
+repositories:
+  - repo1
+env:
+  KEY1: value1
+environments:
+  production:
+    env:
+      DEPLOY_KEY: prod
+  staging: {}
+"#;
+    std::fs::write(&path, yaml).expect("write");
+
+    let config = config::load_config_from_file(&path).expect("load");
+    assert_eq!(config.environments["production"].env["DEPLOY_KEY"], "prod");
+    assert!(config.environments["staging"].env.is_empty());
+}
+
+#[test]
+fn parse_variables() {
+    let dir = tempdir().expect("tempdir");
+    let path = dir.path().join("vars.yaml");
+    let yaml = r#"
+org: example
+repositories:
+  - repo1
+env: {}
+vars:
+  AWS_REGION: eu-west-1
+org_vars:
+  FEATURE_FLAG:
+    value: "on"
+    visibility: all
+environments:
+  production:
+    vars:
+      STAGE: prod
+"#;
+    std::fs::write(&path, yaml).expect("write");
+
+    let config = config::load_config_from_file(&path).expect("load");
+    assert_eq!(config.vars["AWS_REGION"], "eu-west-1");
+    assert_eq!(config.org_vars["FEATURE_FLAG"].value, "on");
+    assert_eq!(config.org_vars["FEATURE_FLAG"].visibility, Visibility::All);
+    assert_eq!(config.environments["production"].vars["STAGE"], "prod");
+}
+
+#[test]
+fn parse_secret_targets() {
+    let dir = tempdir().expect("tempdir");
+    let path = dir.path().join("targets.yaml");
+    let yaml = r#"
+org: example
+repositories:
+  - repo1
+env:
+  NPM_TOKEN: npm
+  API_KEY: key
+secret_options:
+  NPM_TOKEN:
+    targets: [actions, dependabot]
+org_secrets:
+  REGISTRY_TOKEN:
+    value: token
+    targets: [dependabot, codespaces]
+"#;
+    std::fs::write(&path, yaml).expect("write");
+
+    let config = config::load_config_from_file(&path).expect("load");
+    assert_eq!(
+        config.secret_targets("NPM_TOKEN"),
+        &[SecretTarget::Actions, SecretTarget::Dependabot]
+    );
+    assert_eq!(config.secret_targets("API_KEY"), &[SecretTarget::Actions]);
+    assert_eq!(
+        config.org_secrets["REGISTRY_TOKEN"].targets,
+        vec![SecretTarget::Dependabot, SecretTarget::Codespaces]
+    );
+}
+
+#[test]
+fn secret_options_must_reference_env() {
+    let dir = tempdir().expect("tempdir");
+    let path = dir.path().join("targets.yaml");
+    let yaml = r#"
+org: example
+repositories: []
+env: {}
+secret_options:
+  MISSING:
+    targets: [dependabot]
+"#;
+    std::fs::write(&path, yaml).expect("write");
+
+    let err = config::load_config_from_file(&path).unwrap_err();
+    assert!(matches!(err, ConfigError::Invalid(_)));
+}
+
+#[test]
+fn invalid_protected_pattern_returns_error() {
+    let dir = tempdir().expect("tempdir");
+    let path = dir.path().join("protected.yaml");
+    let yaml = r#"
+org: example
+repositories: []
+env: {}
+protected:
+  - "TERRAFORM_[*"
+"#;
+    std::fs::write(&path, yaml).expect("write");
+
+    let err = config::load_config_from_file(&path).unwrap_err();
+    assert!(matches!(err, ConfigError::Invalid(_)));
+}
+
+#[test]
+fn invalid_repository_pattern_returns_error() {
+    let dir = tempdir().expect("tempdir");
+    let path = dir.path().join("config.yaml");
+    let yaml = r#"
+org: example
+select_repositories:
+  names: ["svc-[*"]
+env: {}
+"#;
+    std::fs::write(&path, yaml).expect("write");
+
+    let result = config::load_config_from_file(&path);
+    assert!(matches!(result, Err(ConfigError::Invalid(_))));
+}
+
+#[test]
+fn repo_secrets_apply_filters_and_overrides() {
+    let config: Config = serde_yaml::from_str(
+        r#"
+org: example
+repositories: [repo-a, billing-api]
+env:
+  DB_URL: shared
+  STRIPE_KEY: sk
+secret_options:
+  STRIPE_KEY:
+    repositories: ["billing-*"]
+vars:
+  REGION: eu
+overrides:
+  Repo-A:
+    env:
+      DB_URL: repo-a-db
+    vars:
+      REGION: us
+"#,
+    )
+    .expect("parse");
+    config.validate().expect("valid");
+
+    let repo_a = config.repo_secrets("repo-a");
+    assert_eq!(repo_a.len(), 1);
+    assert_eq!(repo_a["DB_URL"], "repo-a-db");
+    assert_eq!(config.repo_vars("repo-a")["REGION"], "us");
+
+    let billing = config.repo_secrets("billing-api");
+    assert_eq!(billing["DB_URL"], "shared");
+    assert_eq!(billing["STRIPE_KEY"], "sk");
+    assert_eq!(config.repo_vars("billing-api")["REGION"], "eu");
+}
+
+#[test]
+fn extends_inherits_and_overrides_base_values() {
+    let dir = tempdir().expect("tempdir");
+    std::fs::write(
+        dir.path().join("base.yaml"),
+        "org: example\nrepositories: [repo1]\nenv:\n  API_KEY: base\n  DB_URL: base-db\n",
+    )
+    .expect("write");
+    std::fs::write(
+        dir.path().join("prod.yaml"),
+        "extends: base.yaml\nenv:\n  DB_URL: prod-db\n",
+    )
+    .expect("write");
+
+    let config = config::load_config_from_file(dir.path().join("prod.yaml")).expect("load");
+    assert_eq!(config.org, "example");
+    assert_eq!(config.repositories, vec!["repo1"]);
+    assert_eq!(config.env["API_KEY"], "base");
+    assert_eq!(config.env["DB_URL"], "prod-db");
+}
+
+#[test]
+fn references_list_extended_and_included_files() {
+    let dir = tempdir().expect("tempdir");
+    let path = dir.path().join("prod.yaml");
+    std::fs::write(
+        &path,
+        "extends: base.yaml\ninclude: [shared/a.yaml, b.yaml]\nenv:\n  TOKEN: prod\n",
+    )
+    .expect("write");
+
+    let references = config::references(&path).expect("references");
+    assert_eq!(
+        references,
+        vec![
+            dir.path().join("base.yaml"),
+            dir.path().join("shared/a.yaml"),
+            dir.path().join("b.yaml"),
+        ]
+    );
+}
+
+#[test]
+fn conflicting_includes_name_both_files() {
+    let dir = tempdir().expect("tempdir");
+    std::fs::write(dir.path().join("a.yaml"), "env:\n  TOKEN: a\n").expect("write");
+    std::fs::write(dir.path().join("b.yaml"), "env:\n  TOKEN: b\n").expect("write");
+    std::fs::write(
+        dir.path().join("config.yaml"),
+        "org: example\nrepositories: []\ninclude: [a.yaml, b.yaml]\n",
+    )
+    .expect("write");
+
+    let err = config::load_config_from_file(dir.path().join("config.yaml")).unwrap_err();
+    match err {
+        ConfigError::Conflict { key, first, second } => {
+            assert_eq!(key, "env.TOKEN");
+            assert!(first.ends_with("a.yaml"));
+            assert!(second.ends_with("b.yaml"));
+        }
+        other => panic!("expected a conflict, got {:?}", other),
+    }
+}
+
+#[test]
+fn extends_cycle_is_detected() {
+    let dir = tempdir().expect("tempdir");
+    std::fs::write(dir.path().join("a.yaml"), "extends: b.yaml\n").expect("write");
+    std::fs::write(dir.path().join("b.yaml"), "extends: a.yaml\n").expect("write");
+
+    let err = config::load_config_from_file(dir.path().join("a.yaml")).unwrap_err();
+    assert!(matches!(err, ConfigError::Cycle(_)));
+}
@@ -1,4 +1,4 @@
-use gsm::crypto;
+use gsm::crypto::{self, Kdf};
 use pbkdf2::pbkdf2_hmac;
 use sha2::Sha256;
 
@@ -36,3 +36,616 @@ fn derive_key_matches_pbkdf2() {
     pbkdf2_hmac::<Sha256>(password, salt, 100_000, &mut expected);
     assert_eq!(derived.to_vec(), expected.to_vec());
 }
+
+/// Cheap Argon2id parameters so tests stay fast
+fn test_kdf() -> Kdf {
+    Kdf::Argon2id {
+        memory_kib: 1024,
+        time: 1,
+        parallelism: 1,
+    }
+}
+
+fn password_protection() -> gsm::cli::crypto_ops::Protection {
+    gsm::cli::crypto_ops::Protection::Password(test_kdf())
+}
+
+fn credentials(password: &[u8]) -> gsm::cli::crypto_ops::Credentials {
+    gsm::cli::crypto_ops::Credentials {
+        password: Some(password.to_vec()),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn argon2id_keys_depend_on_their_parameters() {
+    let salt = crypto::generate_salt();
+    let key = test_kdf().derive(b"password", &salt).expect("derive");
+    assert_eq!(key, test_kdf().derive(b"password", &salt).expect("derive"));
+
+    let slower = Kdf::Argon2id {
+        memory_kib: 1024,
+        time: 2,
+        parallelism: 1,
+    };
+    assert_ne!(key, slower.derive(b"password", &salt).expect("derive"));
+    assert!(
+        Kdf::Argon2id {
+            memory_kib: 1,
+            time: 1,
+            parallelism: 1
+        }
+        .derive(b"password", &salt)
+        .is_err()
+    );
+}
+
+#[test]
+fn encrypted_configs_round_trip() {
+    use gsm::cli::crypto_ops;
+    use gsm::config::Config;
+
+    let config: Config = serde_yaml::from_str(
+        "org: example\nenv:\n  TOKEN: secret\norg_secrets:\n  SHARED:\n    value: shared\nvars:\n  REGION: eu\n",
+    )
+    .expect("parse");
+    let encrypted = crypto_ops::encrypt_config(config, b"password").expect("encrypt");
+    assert_ne!(encrypted.env["TOKEN"].ciphertext, "secret");
+    assert_eq!(encrypted.vars["REGION"], "eu");
+
+    let config = crypto_ops::decrypt_config(encrypted, b"password").expect("decrypt");
+    assert_eq!(config.env["TOKEN"], "secret");
+    assert_eq!(config.org_secrets["SHARED"].value, "shared");
+}
+
+#[test]
+fn encrypted_configs_carry_a_mac_over_their_typed_form() {
+    use gsm::cli::crypto_ops::{self, Credentials};
+    use gsm::config::{Config, EncryptedConfig};
+
+    let config: Config = serde_yaml::from_str(
+        "org: example\nenv:\n  TOKEN: secret\noverrides:\n  app:\n    env:\n      TOKEN: other\n",
+    )
+    .expect("parse");
+    let encrypted = crypto_ops::encrypt_config(config, b"password").expect("encrypt");
+    let yaml = serde_yaml::to_string(&encrypted).expect("serialize");
+
+    // Written out, the typed config is an ordinary encrypted file
+    let mut document: serde_yaml::Value = serde_yaml::from_str(&yaml).expect("parse");
+    crypto_ops::decrypt_document(&mut document, &mut Credentials::with_password(b"password"))
+        .expect("decrypt");
+    assert_eq!(document["overrides"]["app"]["env"]["TOKEN"], "other");
+
+    let mut tampered: EncryptedConfig = serde_yaml::from_str(&yaml).expect("parse");
+    tampered.org = "elsewhere".to_string();
+    assert!(crypto_ops::decrypt_config(tampered, b"password").is_err());
+}
+
+#[test]
+fn reencrypt_keeps_ciphertext_of_unchanged_values() {
+    use gsm::cli::crypto_ops;
+    use serde_yaml::Value;
+
+    let key = b"supersecret";
+    let mut encrypted: Value =
+        serde_yaml::from_str("org: example\nenv:\n  KEEP: same\n  EDIT: old\n").expect("parse");
+    crypto_ops::encrypt_document(
+        &mut encrypted,
+        &mut credentials(key),
+        &password_protection(),
+    )
+    .expect("encrypt");
+
+    let mut document = encrypted.clone();
+    let previous = crypto_ops::decrypt_document_tracked(&mut document, &mut credentials(key))
+        .expect("decrypt");
+    document["env"]["EDIT"] = Value::String("new".to_string());
+    crypto_ops::reencrypt_document(
+        &mut document,
+        &previous,
+        &mut credentials(key),
+        &password_protection(),
+    )
+    .expect("reencrypt");
+
+    assert_eq!(document["env"]["KEEP"], encrypted["env"]["KEEP"]);
+    assert_ne!(document["env"]["EDIT"], encrypted["env"]["EDIT"]);
+    crypto_ops::decrypt_document(&mut document, &mut credentials(key)).expect("decrypt");
+    assert_eq!(document["env"]["EDIT"], "new");
+}
+
+/// A config document as written before file keys, with a per-value salt
+fn legacy_document(password: &[u8]) -> serde_yaml::Value {
+    use base64::{Engine as _, engine::general_purpose};
+
+    let salt = crypto::generate_salt();
+    let key = crypto::derive_key(password, &salt);
+    let (nonce, ciphertext) = crypto::encrypt_with_key(b"legacy", &key, &[]).expect("encrypt");
+    serde_yaml::from_str(&format!(
+        "org: example\nenv:\n  OLD:\n    salt: {}\n    nonce: {}\n    ciphertext: {}\n",
+        general_purpose::STANDARD.encode(&salt),
+        general_purpose::STANDARD.encode(&nonce),
+        general_purpose::STANDARD.encode(&ciphertext),
+    ))
+    .expect("parse")
+}
+
+#[test]
+fn values_with_their_own_salt_still_decrypt() {
+    use gsm::cli::crypto_ops;
+    use serde_yaml::Value;
+
+    let password = b"supersecret";
+    let mut document = legacy_document(password);
+
+    // New values in a legacy file use a file key next to the old ones
+    let previous = crypto_ops::decrypt_document_tracked(&mut document, &mut credentials(password))
+        .expect("decrypt");
+    document["env"]["NEW"] = Value::String("fresh".to_string());
+    crypto_ops::reencrypt_document(
+        &mut document,
+        &previous,
+        &mut credentials(password),
+        &password_protection(),
+    )
+    .expect("reencrypt");
+    assert!(document[crypto_ops::METADATA_KEY]["kdf"]["salt"].is_string());
+    assert!(document["env"]["OLD"]["salt"].is_string());
+    assert!(document["env"]["NEW"].get("salt").is_none());
+
+    crypto_ops::decrypt_document(&mut document, &mut credentials(password)).expect("decrypt");
+    assert_eq!(document["env"]["OLD"], "legacy");
+    assert_eq!(document["env"]["NEW"], "fresh");
+    assert!(document.get(crypto_ops::METADATA_KEY).is_none());
+}
+
+#[test]
+fn migration_rewrites_legacy_files_in_the_newest_format() {
+    use gsm::cli::crypto_ops;
+
+    let password = b"supersecret";
+    let mut document = legacy_document(password);
+    assert_eq!(crypto_ops::format_version(&document).expect("version"), 1);
+    assert!(crypto_ops::needs_migration(&mut document, &password_protection()).expect("check"));
+
+    crypto_ops::decrypt_document(&mut document, &mut credentials(password)).expect("decrypt");
+    crypto_ops::encrypt_document(
+        &mut document,
+        &mut credentials(password),
+        &password_protection(),
+    )
+    .expect("encrypt");
+    assert_eq!(
+        crypto_ops::format_version(&document).expect("version"),
+        crypto_ops::FORMAT_VERSION
+    );
+    assert!(!crypto_ops::needs_migration(&mut document, &password_protection()).expect("check"));
+    assert_eq!(
+        document[crypto_ops::METADATA_KEY]["kdf"]["name"],
+        "argon2id"
+    );
+    // Switching KDFs also goes through a migration
+    let pbkdf2 = Kdf::Pbkdf2Sha256 { iterations: 1000 };
+    assert!(
+        crypto_ops::needs_migration(&mut document, &crypto_ops::Protection::Password(pbkdf2))
+            .expect("check")
+    );
+
+    // Files from a newer gsm are refused rather than misread
+    document[crypto_ops::METADATA_KEY]["version"] = (crypto_ops::FORMAT_VERSION + 1).into();
+    let error =
+        crypto_ops::decrypt_document(&mut document, &mut credentials(password)).unwrap_err();
+    assert!(error.to_string().contains("upgrade gsm"), "{}", error);
+}
+
+#[test]
+fn ciphertexts_moved_to_another_key_fail_to_decrypt() {
+    use gsm::cli::crypto_ops;
+
+    let password = b"supersecret";
+    let mut document: serde_yaml::Value =
+        serde_yaml::from_str("org: example\nenv:\n  DEV_TOKEN: dev\n  PROD_TOKEN: prod\n")
+            .expect("parse");
+    crypto_ops::encrypt_document(
+        &mut document,
+        &mut credentials(password),
+        &password_protection(),
+    )
+    .expect("encrypt");
+
+    // The file MAC catches a swap first, so decrypt the value directly
+    let cipher = crypto_ops::FileCipher::open(&document, &mut credentials(password)).expect("open");
+    let dev_token = &document["env"]["DEV_TOKEN"];
+    assert_eq!(
+        cipher.decrypt("env.DEV_TOKEN", dev_token).expect("decrypt"),
+        "dev"
+    );
+    let error = cipher.decrypt("env.PROD_TOKEN", dev_token).unwrap_err();
+    assert!(error.to_string().contains("env.PROD_TOKEN"), "{}", error);
+}
+
+#[test]
+fn version_2_files_decrypt_without_associated_data() {
+    use base64::{Engine as _, engine::general_purpose};
+    use gsm::cli::crypto_ops;
+
+    let password = b"supersecret";
+    let salt = crypto::generate_salt();
+    let key = test_kdf().derive(password, &salt).expect("derive");
+    let (nonce, ciphertext) = crypto::encrypt_with_key(b"v2", &key, &[]).expect("encrypt");
+    let mut document: serde_yaml::Value = serde_yaml::from_str(&format!(
+        "org: example\nenv:\n  TOKEN:\n    nonce: {}\n    ciphertext: {}\n\
+         gsm:\n  version: 2\n  kdf:\n    name: argon2id\n    memory_kib: 1024\n    \
+         time: 1\n    parallelism: 1\n    salt: {}\n  cipher: aes-256-gcm\n",
+        general_purpose::STANDARD.encode(&nonce),
+        general_purpose::STANDARD.encode(&ciphertext),
+        general_purpose::STANDARD.encode(&salt),
+    ))
+    .expect("parse");
+
+    assert!(crypto_ops::needs_migration(&mut document, &password_protection()).expect("check"));
+    crypto_ops::decrypt_document(&mut document, &mut credentials(password)).expect("decrypt");
+    assert_eq!(document["env"]["TOKEN"], "v2");
+}
+
+#[test]
+fn salt_only_metadata_reads_as_version_2_with_pbkdf2() {
+    use base64::{Engine as _, engine::general_purpose};
+    use gsm::cli::crypto_ops;
+
+    let password = b"supersecret";
+    let salt = crypto::generate_salt();
+    let key = crypto::derive_key(password, &salt);
+    let (nonce, ciphertext) = crypto::encrypt_with_key(b"early", &key, &[]).expect("encrypt");
+    let mut document: serde_yaml::Value = serde_yaml::from_str(&format!(
+        "org: example\nenv:\n  TOKEN:\n    nonce: {}\n    ciphertext: {}\ngsm:\n  salt: {}\n",
+        general_purpose::STANDARD.encode(&nonce),
+        general_purpose::STANDARD.encode(&ciphertext),
+        general_purpose::STANDARD.encode(&salt),
+    ))
+    .expect("parse");
+
+    assert_eq!(crypto_ops::format_version(&document).expect("version"), 2);
+    assert!(crypto_ops::needs_migration(&mut document, &password_protection()).expect("check"));
+    crypto_ops::decrypt_document(&mut document, &mut credentials(password)).expect("decrypt");
+    assert_eq!(document["env"]["TOKEN"], "early");
+}
+
+#[test]
+fn kdf_parameters_are_bounded() {
+    assert!(Kdf::default().check_strength().is_ok());
+    assert!(test_kdf().check_strength().is_err());
+    let weak_pbkdf2 = Kdf::Pbkdf2Sha256 { iterations: 1_000 };
+    assert!(weak_pbkdf2.check_strength().is_err());
+
+    assert!(Kdf::default().check_cost().is_ok());
+    let huge = Kdf::Argon2id {
+        memory_kib: u32::MAX,
+        time: 1,
+        parallelism: 1,
+    };
+    assert!(huge.check_cost().is_err());
+    let slow = Kdf::Pbkdf2Sha256 {
+        iterations: u32::MAX,
+    };
+    assert!(slow.check_cost().is_err());
+}
+
+#[test]
+fn files_with_costly_kdf_parameters_are_refused_before_deriving() {
+    use gsm::cli::crypto_ops;
+
+    let mut document = serde_yaml::Value::Mapping(Default::default());
+    document["org"] = "example".into();
+    crypto_ops::encrypt_document(
+        &mut document,
+        &mut credentials(b"pw"),
+        &password_protection(),
+    )
+    .expect("encrypt");
+    document[crypto_ops::METADATA_KEY]["kdf"]["memory_kib"] = u32::MAX.into();
+
+    let error = crypto_ops::decrypt_document(&mut document, &mut credentials(b"pw")).unwrap_err();
+    assert!(error.to_string().contains("costlier"), "{}", error);
+}
+
+#[test]
+fn canonical_form_is_typed_and_ignores_key_order_and_mac() {
+    use gsm::cli::crypto_ops::canonical_bytes;
+
+    let canonical = |yaml: &str| {
+        let document: serde_yaml::Value = serde_yaml::from_str(yaml).expect("parse");
+        canonical_bytes(&document, 7).expect("canonical")
+    };
+
+    assert_eq!(
+        canonical("b: 1\na: [x, true, null]\n"),
+        b"m2:s1:al3:s1:xtns1:bd1:1".to_vec()
+    );
+    assert_eq!(canonical("b: 1\na: x\n"), canonical("a: x\nb: 1\n"));
+    assert_eq!(
+        canonical("a: x\ngsm:\n  version: 7\n"),
+        canonical("a: x\ngsm:\n  version: 7\n  mac: abc\n")
+    );
+    assert_ne!(canonical("1: x\n"), canonical("\"1\": x\n"));
+    assert_ne!(canonical("a: 1\n"), canonical("a: \"1\"\n"));
+    assert_ne!(canonical("a: true\n"), canonical("a: \"true\"\n"));
+    assert_ne!(canonical("a: [bc]\n"), canonical("a: [b, c]\n"));
+}
+
+#[test]
+fn older_versions_keep_their_sorted_json_canonical_form() {
+    use gsm::cli::crypto_ops::canonical_bytes;
+
+    let document: serde_yaml::Value =
+        serde_yaml::from_str("org: example\nenv:\n  B: 2\n  A: x\ngsm:\n  mac: abc\n")
+            .expect("parse");
+    assert_eq!(
+        canonical_bytes(&document, 6).expect("canonical"),
+        br#"{"env":{"A":"x","B":2},"gsm":{},"org":"example"}"#.to_vec()
+    );
+}
+
+#[test]
+fn tampering_with_an_encrypted_file_is_detected() {
+    use gsm::cli::crypto_ops;
+
+    let password = b"supersecret";
+    let mut document: serde_yaml::Value = serde_yaml::from_str(
+        "org: example\nrepositories: [app]\nenv:\n  API_KEY: key\n  DB_PASSWORD: password\n",
+    )
+    .expect("parse");
+    crypto_ops::encrypt_document(
+        &mut document,
+        &mut credentials(password),
+        &password_protection(),
+    )
+    .expect("encrypt");
+
+    // Key order and formatting do not matter
+    let mut entries: Vec<_> = document
+        .as_mapping()
+        .expect("mapping")
+        .clone()
+        .into_iter()
+        .collect();
+    entries.reverse();
+    let mut reordered: serde_yaml::Value =
+        entries.into_iter().collect::<serde_yaml::Mapping>().into();
+    crypto_ops::decrypt_document(&mut reordered, &mut credentials(password)).expect("decrypt");
+
+    let mut redirected = document.clone();
+    redirected["repositories"][0] = "attacker-repo".into();
+    let error =
+        crypto_ops::decrypt_document(&mut redirected, &mut credentials(password)).unwrap_err();
+    assert!(
+        error.to_string().contains("Integrity check failed"),
+        "{}",
+        error
+    );
+
+    let mut removed = document.clone();
+    removed["env"]
+        .as_mapping_mut()
+        .expect("env")
+        .remove("DB_PASSWORD");
+    assert!(crypto_ops::decrypt_document(&mut removed, &mut credentials(password)).is_err());
+
+    // Claiming an older version to skip the MAC breaks every value instead
+    let mut downgraded = redirected;
+    let metadata = downgraded[crypto_ops::METADATA_KEY]
+        .as_mapping_mut()
+        .expect("metadata");
+    metadata.remove("mac");
+    metadata.insert("version".into(), 3.into());
+    assert!(crypto_ops::decrypt_document(&mut downgraded, &mut credentials(password)).is_err());
+}
+
+#[test]
+fn recipients_decrypt_with_their_identity_only() {
+    use base64::{Engine as _, engine::general_purpose};
+    use gsm::cli::crypto_ops::{self, Credentials, Protection};
+    use gsm::crypto::Identity;
+
+    let alice = Identity::generate().expect("identity");
+    let bob = Identity::generate().expect("identity");
+    let recipients = vec![
+        general_purpose::STANDARD.encode(alice.public_key()),
+        general_purpose::STANDARD.encode(bob.public_key()),
+    ];
+    let protection = Protection::Recipients(recipients.clone());
+    let mut document: serde_yaml::Value =
+        serde_yaml::from_str("org: example\nenv:\n  TOKEN: shared\n").expect("parse");
+    // Encrypting to recipients needs no password or identity
+    crypto_ops::encrypt_document(&mut document, &mut Credentials::default(), &protection)
+        .expect("encrypt");
+    assert!(document[crypto_ops::METADATA_KEY].get("kdf").is_none());
+    assert!(!crypto_ops::needs_migration(&mut document, &protection).expect("check"));
+
+    for identity in [alice, bob] {
+        let mut decrypted = document.clone();
+        crypto_ops::decrypt_document(
+            &mut decrypted,
+            &mut Credentials {
+                identity: Some(identity),
+                ..Credentials::default()
+            },
+        )
+        .expect("decrypt");
+        assert_eq!(decrypted["env"]["TOKEN"], "shared");
+    }
+
+    let outsider = Identity::generate().expect("identity");
+    let error = crypto_ops::decrypt_document(
+        &mut document.clone(),
+        &mut Credentials {
+            identity: Some(outsider),
+            ..Credentials::default()
+        },
+    )
+    .unwrap_err();
+    assert!(error.to_string().contains("not a recipient"), "{}", error);
+
+    // Revoking a recipient calls for a migration under a new key
+    let revoked = Protection::Recipients(recipients[..1].to_vec());
+    assert!(crypto_ops::needs_migration(&mut document, &revoked).expect("check"));
+}
+
+#[cfg(unix)]
+#[test]
+fn migrate_changes_the_kind_of_protection_only_when_asked() {
+    use base64::{Engine as _, engine::general_purpose};
+    use gsm::cli::crypto_ops::{self, Credentials, Protection};
+    use gsm::crypto::Identity;
+    use std::os::unix::fs::PermissionsExt;
+    use std::process::Command;
+
+    let dir = tempfile::tempdir().expect("tempdir");
+    let identity = Identity::generate().expect("identity");
+    let identity_path = dir.path().join("identity");
+    std::fs::write(
+        &identity_path,
+        general_purpose::STANDARD.encode(identity.secret_key()),
+    )
+    .expect("write identity");
+    std::fs::set_permissions(&identity_path, std::fs::Permissions::from_mode(0o600))
+        .expect("chmod");
+
+    let mut document: serde_yaml::Value =
+        serde_yaml::from_str("org: example\nenv:\n  TOKEN: secret\n").expect("parse");
+    crypto_ops::encrypt_document(
+        &mut document,
+        &mut Credentials::with_password(b"password"),
+        &Protection::Password(Kdf::Argon2id {
+            memory_kib: 1024,
+            time: 1,
+            parallelism: 1,
+        }),
+    )
+    .expect("encrypt");
+    let file = dir.path().join("prod.yaml");
+    let original = serde_yaml::to_string(&document).expect("yaml");
+    std::fs::write(&file, &original).expect("write");
+    // The project now shares its files with a recipient
+    std::fs::write(
+        dir.path().join(".gsm"),
+        format!(
+            "recipients:\n  - {}\n",
+            general_purpose::STANDARD.encode(identity.public_key())
+        ),
+    )
+    .expect("write .gsm");
+
+    let migrate = |change_protection: bool| {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_gsm"));
+        command
+            .arg("migrate")
+            .arg(&file)
+            .env("ENCRYPTION_KEY", "password")
+            .env("GSM_IDENTITY", &identity_path);
+        if change_protection {
+            command.arg("--change-protection");
+        }
+        command.output().expect("run gsm")
+    };
+
+    let refused = migrate(false);
+    assert!(!refused.status.success());
+    let stderr = String::from_utf8_lossy(&refused.stderr);
+    assert!(stderr.contains("--change-protection"), "{}", stderr);
+    assert_eq!(std::fs::read_to_string(&file).expect("read"), original);
+
+    let switched = migrate(true);
+    let stdout = String::from_utf8_lossy(&switched.stdout);
+    assert!(
+        switched.status.success(),
+        "{}",
+        String::from_utf8_lossy(&switched.stderr)
+    );
+    assert!(
+        stdout.contains("from a password to recipients"),
+        "{}",
+        stdout
+    );
+    let mut migrated: serde_yaml::Value =
+        serde_yaml::from_str(&std::fs::read_to_string(&file).expect("read")).expect("parse");
+    assert_eq!(
+        crypto_ops::protection_kind(&migrated).expect("kind"),
+        "recipients"
+    );
+    crypto_ops::decrypt_document(
+        &mut migrated,
+        &mut Credentials {
+            identity: Some(identity),
+            ..Credentials::default()
+        },
+    )
+    .expect("decrypt");
+    assert_eq!(migrated["env"]["TOKEN"], "secret");
+}
+
+#[test]
+fn rekey_moves_a_file_to_the_new_password() {
+    use gsm::cli::crypto_ops;
+
+    let mut document: serde_yaml::Value =
+        serde_yaml::from_str("org: example\nenv:\n  TOKEN: secret\n").expect("parse");
+    crypto_ops::encrypt_document(
+        &mut document,
+        &mut credentials(b"old"),
+        &password_protection(),
+    )
+    .expect("encrypt");
+
+    let rekeyed = crypto_ops::rekey_document(
+        &mut document,
+        &mut credentials(b"old"),
+        &mut credentials(b"new"),
+    )
+    .expect("rekey");
+    assert!(rekeyed);
+    // The KDF and its parameters carry over
+    assert_eq!(
+        document[crypto_ops::METADATA_KEY]["kdf"]["memory_kib"],
+        1024
+    );
+    assert!(crypto_ops::decrypt_document(&mut document.clone(), &mut credentials(b"old")).is_err());
+    crypto_ops::decrypt_document(&mut document, &mut credentials(b"new")).expect("decrypt");
+    assert_eq!(document["env"]["TOKEN"], "secret");
+}
+
+#[test]
+fn encrypted_files_only_inherit_from_encrypted_files() {
+    use gsm::cli::crypto_ops;
+
+    let dir = tempfile::tempdir().expect("tempdir");
+    let encrypt_to = |name: &str, yaml: &str| {
+        let mut document: serde_yaml::Value = serde_yaml::from_str(yaml).expect("parse");
+        crypto_ops::encrypt_document(
+            &mut document,
+            &mut credentials(b"pw"),
+            &password_protection(),
+        )
+        .expect("encrypt");
+        let yaml = serde_yaml::to_string(&document).expect("serialize");
+        std::fs::write(dir.path().join(name), yaml).expect("write");
+    };
+    encrypt_to("base.yaml", "org: acme\nrepositories: [api]\n");
+    encrypt_to("prod.yaml", "extends: base.yaml\nenv:\n  TOKEN: secret\n");
+    let prod = dir.path().join("prod.yaml");
+    let config = crypto_ops::load_config(&prod, &mut credentials(b"pw")).expect("load");
+    assert_eq!(config.org, "acme");
+    assert_eq!(config.env["TOKEN"], "secret");
+
+    // Whoever can edit the base cannot send the secrets to another org
+    let base = dir.path().join("base.yaml");
+    let content = std::fs::read_to_string(&base).expect("read");
+    std::fs::write(&base, content.replace("org: acme", "org: evil")).expect("write");
+    let error = crypto_ops::load_config(&prod, &mut credentials(b"pw")).unwrap_err();
+    assert!(error.to_string().contains("Integrity"), "{}", error);
+
+    std::fs::write(&base, "org: evil\nrepositories: [api]\n").expect("write");
+    let error = crypto_ops::load_config(&prod, &mut credentials(b"pw")).unwrap_err();
+    assert!(error.to_string().contains("Integrity"), "{}", error);
+    assert!(error.to_string().contains("not encrypted"), "{}", error);
+}
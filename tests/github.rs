@@ -0,0 +1,282 @@
+mod common;
+
+use std::time::Duration;
+
+use base64::{Engine as _, engine::general_purpose};
+
+use common::{Reply, Stub};
+use gsm::config::SecretTarget;
+use gsm::github::{self, GithubClient, RetryPolicy};
+
+#[test]
+fn backoff_grows_exponentially_within_jitter_bounds() {
+    let policy = RetryPolicy {
+        max_retries: 5,
+        base_delay: Duration::from_millis(100),
+        max_delay: Duration::from_secs(60),
+    };
+    for attempt in 0..5 {
+        let full = Duration::from_millis(100 * 2u64.pow(attempt));
+        let delay = policy.backoff(attempt);
+        assert!(
+            delay <= full,
+            "attempt {}: {:?} > {:?}",
+            attempt,
+            delay,
+            full
+        );
+        assert!(
+            delay >= full / 2,
+            "attempt {}: {:?} < {:?}",
+            attempt,
+            delay,
+            full / 2
+        );
+    }
+}
+
+#[test]
+fn backoff_is_capped_by_max_delay() {
+    let policy = RetryPolicy {
+        max_retries: 50,
+        base_delay: Duration::from_secs(1),
+        max_delay: Duration::from_secs(10),
+    };
+    assert!(policy.backoff(40) <= Duration::from_secs(10));
+}
+
+#[test]
+fn encrypt_github_secret_rejects_bad_public_key() {
+    let err = github::encrypt_github_secret("c2hvcnQ=", "value").unwrap_err();
+    assert!(matches!(err, github::GithubError::InvalidPublicKeyLength));
+}
+
+#[test]
+fn github_app_rejects_invalid_private_key() {
+    let result = github::GithubApp::new(
+        "123".to_string(),
+        b"not a pem key",
+        "acme".to_string(),
+        None,
+    );
+    assert!(matches!(result, Err(github::GithubError::AppAuth(_))));
+}
+
+/// Private key of a GitHub App that exists only in these tests
+const APP_KEY: &[u8] = include_bytes!("fixtures/github_app.pem");
+
+/// Answers an app's installation lookup and hands out installation tokens
+/// `inst-1`, `inst-2`, ... in turn; `repo` answers everything else
+fn app_stub<F>(repo: F) -> Stub
+where
+    F: Fn(&common::Request) -> (u16, String) + Send + Sync + 'static,
+{
+    let issued = std::sync::atomic::AtomicUsize::new(0);
+    Stub::start(move |request| {
+        if request.path == "/orgs/acme/installation" {
+            (200, serde_json::json!({ "id": 42 }).to_string())
+        } else if request.path == "/app/installations/42/access_tokens" {
+            let n = issued.fetch_add(1, std::sync::atomic::Ordering::SeqCst) + 1;
+            let token = format!("inst-{}", n);
+            (201, serde_json::json!({ "token": token }).to_string())
+        } else {
+            repo(request)
+        }
+    })
+}
+
+fn app_client(stub: &Stub) -> GithubClient {
+    let app =
+        github::GithubApp::new("123".to_string(), APP_KEY, "acme".to_string(), None).expect("app");
+    GithubClient::from_app(app, Some(stub.url.clone())).with_retry_policy(quick_retries())
+}
+
+#[tokio::test]
+async fn app_exchanges_its_jwt_for_an_installation_token() {
+    let stub = app_stub(|_| (200, serde_json::json!({ "id": 7 }).to_string()));
+    let client = app_client(&stub);
+
+    assert_eq!(
+        client.get_repository("acme", "api").await.expect("get").id,
+        7
+    );
+    assert_eq!(
+        client.get_repository("acme", "web").await.expect("get").id,
+        7
+    );
+
+    let requests = stub.requests();
+    let calls: Vec<_> = requests
+        .iter()
+        .map(|request| format!("{} {}", request.method, request.path))
+        .collect();
+    assert_eq!(
+        calls,
+        [
+            "GET /orgs/acme/installation",
+            "POST /app/installations/42/access_tokens",
+            "GET /repos/acme/api",
+            "GET /repos/acme/web",
+        ]
+    );
+
+    // The app authenticates with a JWT it issued itself
+    for request in &requests[..2] {
+        let jwt = request
+            .header("authorization")
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .expect("bearer JWT");
+        let claims = jwt.split('.').nth(1).expect("claims");
+        let claims: serde_json::Value = serde_json::from_slice(
+            &general_purpose::URL_SAFE_NO_PAD
+                .decode(claims)
+                .expect("base64"),
+        )
+        .expect("json");
+        assert_eq!(claims["iss"], "123");
+    }
+    // and the installation token is reused until it needs replacing
+    for request in &requests[2..] {
+        assert_eq!(request.header("authorization"), Some("token inst-1"));
+    }
+}
+
+#[tokio::test]
+async fn app_replaces_a_rejected_installation_token_once() {
+    let stub = app_stub(|request| match request.header("authorization") {
+        Some("token inst-1") => (401, "{}".to_string()),
+        _ => (200, serde_json::json!({ "id": 7 }).to_string()),
+    });
+    let client = app_client(&stub);
+
+    assert_eq!(
+        client.get_repository("acme", "api").await.expect("get").id,
+        7
+    );
+    assert_eq!(
+        stub.calls("POST"),
+        [
+            "POST /app/installations/42/access_tokens",
+            "POST /app/installations/42/access_tokens",
+        ]
+    );
+
+    // A token that is rejected right after being issued is not replaced again
+    let stub = app_stub(|_| (401, "{}".to_string()));
+    let client = app_client(&stub);
+    assert!(client.get_repository("acme", "api").await.is_err());
+    assert_eq!(stub.calls("POST").len(), 2);
+    assert_eq!(
+        stub.calls("GET")
+            .iter()
+            .filter(|call| call.starts_with("GET /repos/"))
+            .count(),
+        2
+    );
+}
+
+#[tokio::test]
+async fn org_variable_repository_ids_are_paginated() {
+    let stub = Stub::start(|request| {
+        let repositories: Vec<_> = if request.path.ends_with("page=1") {
+            (1..=100)
+                .map(|id| serde_json::json!({ "id": id }))
+                .collect()
+        } else {
+            vec![serde_json::json!({ "id": 101 })]
+        };
+        (
+            200,
+            serde_json::json!({ "repositories": repositories }).to_string(),
+        )
+    });
+    let client = GithubClient::new("token".to_string(), Some(stub.url.clone()));
+
+    let ids = client
+        .get_org_variable_repository_ids("acme", "REGION")
+        .await
+        .expect("list");
+
+    assert_eq!(ids, (1..=101).collect::<Vec<u64>>());
+    assert_eq!(
+        stub.calls("GET"),
+        [
+            "GET /orgs/acme/actions/variables/REGION/repositories?per_page=100&page=1",
+            "GET /orgs/acme/actions/variables/REGION/repositories?per_page=100&page=2",
+        ]
+    );
+}
+
+#[tokio::test]
+async fn repository_secrets_are_paginated() {
+    let stub = Stub::start(|request| {
+        let count = if request.path.ends_with("page=1") {
+            100
+        } else {
+            1
+        };
+        let secrets: Vec<_> = (0..count)
+            .map(|i| serde_json::json!({ "name": format!("S{}", i), "updated_at": "" }))
+            .collect();
+        (200, serde_json::json!({ "secrets": secrets }).to_string())
+    });
+    let client = GithubClient::new("token".to_string(), Some(stub.url.clone()));
+
+    let secrets = client
+        .list_repo_secrets(SecretTarget::Actions, "acme", "api")
+        .await
+        .expect("list");
+
+    assert_eq!(secrets.len(), 101);
+    assert_eq!(
+        stub.calls("GET"),
+        [
+            "GET /repos/acme/api/actions/secrets?per_page=100&page=1",
+            "GET /repos/acme/api/actions/secrets?per_page=100&page=2",
+        ]
+    );
+}
+
+fn quick_retries() -> RetryPolicy {
+    RetryPolicy {
+        max_retries: 3,
+        base_delay: Duration::from_millis(1),
+        max_delay: Duration::from_secs(5),
+    }
+}
+
+#[tokio::test]
+async fn server_errors_are_not_retried_for_post() {
+    let stub = Stub::start(|_| (502, "{}".to_string()));
+    let client = GithubClient::new("token".to_string(), Some(stub.url.clone()))
+        .with_retry_policy(quick_retries());
+    let body = github::VariableBody {
+        name: "REGION",
+        value: "eu",
+        visibility: None,
+        selected_repository_ids: None,
+    };
+
+    let scope = github::VariableScope::Org { org: "acme" };
+    assert!(client.create_variable(scope, &body).await.is_err());
+    assert_eq!(stub.calls("POST").len(), 1);
+
+    assert!(client.update_variable(scope, &body).await.is_err());
+    assert_eq!(stub.calls("PATCH").len(), 4);
+}
+
+#[tokio::test]
+async fn waits_beyond_max_delay_fail_instead_of_sleeping() {
+    let stub = Stub::start(|_| Reply {
+        status: 429,
+        headers: vec![("Retry-After", "3600".to_string())],
+        body: "{}".to_string(),
+    });
+    let client = GithubClient::new("token".to_string(), Some(stub.url.clone()))
+        .with_retry_policy(quick_retries());
+
+    let err = client.get_repository("acme", "repo").await.unwrap_err();
+    assert!(matches!(err, github::GithubError::RateLimited(0, _)));
+    assert!(err.to_string().contains("wait 3600s"));
+    assert_eq!(stub.requests().len(), 1);
+}
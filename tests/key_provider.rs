@@ -0,0 +1,138 @@
+use std::fs;
+use tempfile::tempdir;
+
+use gsm::cli::key_provider::{KeyArgs, KeyCommand, KeyFile, KeyProvider, KeySource, Keyring};
+
+#[test]
+fn key_sources_parse_from_their_spec() {
+    assert_eq!(
+        "env:MY_KEY".parse::<KeySource>().expect("parse"),
+        KeySource::Env("MY_KEY".to_string())
+    );
+    assert_eq!(
+        "command:pass show gsm".parse::<KeySource>().expect("parse"),
+        KeySource::Command("pass show gsm".to_string())
+    );
+    assert_eq!(
+        "prompt".parse::<KeySource>().expect("parse"),
+        KeySource::Prompt
+    );
+    assert!("env:".parse::<KeySource>().is_err());
+    assert!("vault:key".parse::<KeySource>().is_err());
+}
+
+#[cfg(unix)]
+#[test]
+fn key_files_must_be_private() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = tempdir().expect("tempdir");
+    let path = dir.path().join("key");
+    fs::write(&path, "from-file\n").expect("write");
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).expect("chmod");
+    let provider = KeyFile { path: path.clone() };
+    let error = provider.key().unwrap_err();
+    assert!(error.to_string().contains("chmod 600"), "{}", error);
+
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o600)).expect("chmod");
+    assert_eq!(provider.key().expect("key"), b"from-file");
+
+    let keyring = dir.path().join("keyring");
+    fs::write(&keyring, "staging: one\nproduction: two\n").expect("write");
+    fs::set_permissions(&keyring, fs::Permissions::from_mode(0o600)).expect("chmod");
+    let provider = Keyring {
+        path: keyring,
+        name: "production".to_string(),
+    };
+    assert_eq!(provider.key().expect("key"), b"two");
+}
+
+#[cfg(unix)]
+#[test]
+fn identity_files_must_be_private() {
+    use base64::{Engine as _, engine::general_purpose};
+    use gsm::cli::crypto_ops::read_identity;
+    use gsm::crypto::Identity;
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = tempdir().expect("tempdir");
+    let path = dir.path().join("identity");
+    let identity = Identity::generate().expect("generate");
+    let secret_key = general_purpose::STANDARD.encode(identity.secret_key());
+    fs::write(&path, format!("# public key: x\n{}\n", secret_key)).expect("write");
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o640)).expect("chmod");
+    let Err(error) = read_identity(&path) else {
+        panic!("a group-readable identity was accepted");
+    };
+    assert!(error.to_string().contains("chmod 600"), "{}", error);
+
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o600)).expect("chmod");
+    let read = read_identity(&path).expect("identity");
+    assert_eq!(read.public_key(), identity.public_key());
+
+    let Err(error) = read_identity(&dir.path().join("missing")) else {
+        panic!("a missing identity was read");
+    };
+    assert!(error.to_string().contains("gsm keygen"), "{}", error);
+}
+
+#[test]
+fn commands_and_gsm_files_provide_keys() {
+    let provider = KeyCommand {
+        command: "echo from-command".to_string(),
+    };
+    assert_eq!(provider.key().expect("key"), b"from-command");
+    let failing = KeyCommand {
+        command: "exit 1".to_string(),
+    };
+    assert!(failing.key().is_err());
+
+    let dir = tempdir().expect("tempdir");
+    fs::write(dir.path().join(".gsm"), "key_source: keyring:staging\n").expect("write");
+    let file = dir.path().join("app.yaml");
+    let args = KeyArgs::default();
+    assert_eq!(
+        args.source(&file).expect("source"),
+        KeySource::Keyring("staging".to_string())
+    );
+    // The flag wins over the `.gsm` file
+    let args = KeyArgs {
+        key_source: Some(KeySource::Command("echo key".to_string())),
+        ..KeyArgs::default()
+    };
+    assert_eq!(
+        args.source(&file).expect("source"),
+        KeySource::Command("echo key".to_string())
+    );
+}
+
+#[test]
+fn gsm_files_cannot_run_key_commands() {
+    let dir = tempdir().expect("tempdir");
+    fs::write(dir.path().join(".gsm"), "key_source: command:echo key\n").expect("write");
+    let nested = dir.path().join("services").join("api");
+    fs::create_dir_all(&nested).expect("mkdir");
+
+    let error = KeyArgs::default()
+        .source(&nested.join("app.yaml"))
+        .unwrap_err();
+    assert!(error.to_string().contains("--key-source"), "{}", error);
+}
+
+#[test]
+fn keygen_needs_a_home_directory_for_its_default_path() {
+    let dir = tempdir().expect("tempdir");
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_gsm"))
+        .arg("keygen")
+        .current_dir(dir.path())
+        .env_remove("HOME")
+        .env_remove("GSM_IDENTITY")
+        .output()
+        .expect("run gsm");
+
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("HOME"), "{}", stderr);
+    // Nothing lands under the working directory instead
+    assert!(!dir.path().join(".gsm").exists());
+}
@@ -0,0 +1,101 @@
+use gsm::cli::keys::SecretKey;
+use serde_yaml::Value;
+
+#[test]
+fn secret_keys_address_every_secret_location() {
+    let document: Value = serde_yaml::from_str(
+        r#"
+env:
+  API_KEY: a
+org_secrets:
+  SHARED:
+    value: s
+environments:
+  production:
+    env:
+      DEPLOY: d
+"#,
+    )
+    .expect("parse");
+
+    let get = |key: &str| {
+        SecretKey::parse(key)
+            .expect("parse key")
+            .get(&document)
+            .cloned()
+    };
+    assert_eq!(get("API_KEY"), Some(Value::from("a")));
+    assert_eq!(get("env.API_KEY"), Some(Value::from("a")));
+    assert_eq!(get("org_secrets.SHARED"), Some(Value::from("s")));
+    assert_eq!(
+        get("environments.production.env.DEPLOY"),
+        Some(Value::from("d"))
+    );
+    assert_eq!(get("MISSING"), None);
+    assert!(SecretKey::parse("vars.REGION").is_err());
+}
+
+#[test]
+fn set_and_unset_touch_only_the_addressed_entry() {
+    let mut document: Value =
+        serde_yaml::from_str("org: example\nenv:\n  KEEP: k\n").expect("parse");
+
+    let key = SecretKey::parse("environments.staging.env.TOKEN").expect("parse key");
+    key.set(&mut document, Value::from("t")).expect("set");
+    assert_eq!(document["environments"]["staging"]["env"]["TOKEN"], "t");
+
+    let org_secret = SecretKey::parse("org_secrets.SHARED").expect("parse key");
+    org_secret
+        .set(&mut document, Value::from("s"))
+        .expect("set");
+    assert!(org_secret.unset(&mut document).expect("unset"));
+    assert!(document["org_secrets"].get("SHARED").is_none());
+    assert!(!org_secret.unset(&mut document).expect("unset"));
+
+    assert_eq!(document["env"]["KEEP"], "k");
+}
+
+#[test]
+fn get_verifies_the_file_before_printing_plaintext_values() {
+    use gsm::cli::crypto_ops::{self, Credentials, Protection};
+    use gsm::cli::get::{self, GetArgs};
+    use gsm::cli::key_provider::{KeyArgs, KeySource};
+    use gsm::crypto::Kdf;
+
+    let dir = tempfile::tempdir().expect("tempdir");
+    let key_path = dir.path().join("key");
+    std::fs::write(&key_path, "password").expect("write key");
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(&key_path, std::fs::Permissions::from_mode(0o600)).expect("chmod");
+    }
+
+    let mut document: Value =
+        serde_yaml::from_str("org: example\nenv:\n  TOKEN: secret\n").expect("parse");
+    let mut credentials = Credentials {
+        password: Some(b"password".to_vec()),
+        ..Default::default()
+    };
+    let protection = Protection::Password(Kdf::Argon2id {
+        memory_kib: 1024,
+        time: 1,
+        parallelism: 1,
+    });
+    crypto_ops::encrypt_document(&mut document, &mut credentials, &protection).expect("encrypt");
+    // A plaintext value slipped into the encrypted file
+    document["env"]["INJECTED"] = Value::from("attacker");
+    let file = dir.path().join("prod.yaml");
+    std::fs::write(&file, serde_yaml::to_string(&document).expect("yaml")).expect("write");
+
+    let error = get::run(&GetArgs {
+        file,
+        key: "INJECTED".to_string(),
+        encryption: KeyArgs {
+            key_source: Some(KeySource::File(key_path)),
+            ..KeyArgs::default()
+        },
+    })
+    .unwrap_err();
+    assert!(error.to_string().contains("Integrity"), "{}", error);
+}
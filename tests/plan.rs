@@ -0,0 +1,265 @@
+mod common;
+
+use common::Stub;
+use gsm::config::{Config, SecretTarget};
+use gsm::github::GithubClient;
+use gsm::plan::{self, Action, Change, Plan, SecretLocation, State, StateEntry};
+use tempfile::tempdir;
+
+#[test]
+fn fingerprint_depends_on_key_and_value() {
+    let dir = tempdir().expect("tempdir");
+    let state = State::load_or_init(&dir.path().join("state.json")).expect("init");
+
+    let fingerprint = state.fingerprint("org/repo/actions/KEY", "value");
+    assert_eq!(
+        fingerprint,
+        state.fingerprint("org/repo/actions/KEY", "value")
+    );
+    assert_ne!(
+        fingerprint,
+        state.fingerprint("org/repo/actions/KEY", "other")
+    );
+    assert_ne!(
+        fingerprint,
+        state.fingerprint("org/repo/dependabot/KEY", "value")
+    );
+    assert!(!fingerprint.contains("value"));
+}
+
+#[test]
+fn state_salt_survives_save_and_load() {
+    let dir = tempdir().expect("tempdir");
+    let path = dir.path().join("state.json");
+    let state = State::load_or_init(&path).expect("init");
+    state.save(&path).expect("save");
+
+    let reloaded = State::load_or_init(&path).expect("load");
+    assert_eq!(reloaded.salt, state.salt);
+    assert_eq!(reloaded.fingerprint("k", "v"), state.fingerprint("k", "v"));
+}
+
+#[test]
+fn fingerprint_key_is_kept_apart_from_the_state() {
+    let dir = tempdir().expect("tempdir");
+    let path = dir.path().join("state.json");
+    let state = State::load_or_init(&path).expect("init");
+    state.save(&path).expect("save");
+
+    let key_path = State::key_path(&path);
+    assert_eq!(key_path, dir.path().join("state.json.key"));
+    let key = std::fs::read_to_string(&key_path).expect("key");
+    assert!(
+        !std::fs::read_to_string(&path)
+            .expect("read")
+            .contains(key.trim())
+    );
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = std::fs::metadata(&key_path)
+            .expect("metadata")
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+
+    std::fs::remove_file(&key_path).expect("remove");
+    let rekeyed = State::load_or_init(&path).expect("load");
+    assert_eq!(rekeyed.salt, state.salt);
+    assert_ne!(rekeyed.fingerprint("k", "v"), state.fingerprint("k", "v"));
+}
+
+#[test]
+fn plan_roundtrips_through_file() {
+    let dir = tempdir().expect("tempdir");
+    let path = dir.path().join("plan.json");
+    let plan = Plan {
+        config: "config.yaml".into(),
+        org: "example".to_string(),
+        salt: "salt".to_string(),
+        changes: vec![Change {
+            location: SecretLocation::Repo {
+                repo: "repo1".to_string(),
+                target: SecretTarget::Dependabot,
+            },
+            name: "NPM_TOKEN".to_string(),
+            action: Action::Create,
+            reason: None,
+            fingerprint: Some("abc".to_string()),
+        }],
+    };
+    plan.save(&path).expect("save");
+
+    let loaded = Plan::load(&path).expect("load");
+    assert!(loaded.has_changes());
+    assert_eq!(loaded.changes[0].location, plan.changes[0].location);
+    assert_eq!(loaded.changes[0].action, Action::Create);
+}
+
+const UPDATED_AT: &str = "2024-01-01T00:00:00Z";
+
+/// Repository secrets `CHANGED`, `SAME` and `OLD` exist on GitHub
+fn github_with_secrets() -> (Stub, GithubClient) {
+    let stub = Stub::start(|request| {
+        if request.method != "GET" {
+            return (204, String::new());
+        }
+        let names: &[&str] = if request.path.starts_with("/repos/acme/api/actions/secrets?") {
+            &["CHANGED", "SAME", "OLD"]
+        } else {
+            &[]
+        };
+        let secrets: Vec<_> = names
+            .iter()
+            .map(|name| serde_json::json!({ "name": name, "updated_at": UPDATED_AT }))
+            .collect();
+        let body = serde_json::json!({ "total_count": secrets.len(), "secrets": secrets });
+        (200, body.to_string())
+    });
+    let client = GithubClient::new("token".to_string(), Some(stub.url.clone()));
+    (stub, client)
+}
+
+fn config() -> Config {
+    serde_yaml::from_str(
+        "org: acme\nrepositories: [api]\nenv:\n  NEW_KEY: new\n  CHANGED: changed\n  SAME: same\n",
+    )
+    .expect("config")
+}
+
+/// A state that last pushed `SAME` as it is now and `CHANGED` with an old value
+fn recorded_state(path: &std::path::Path) -> State {
+    let mut state = State::load_or_init(path).expect("init");
+    for (name, value) in [("SAME", "same"), ("CHANGED", "before")] {
+        let key = format!("acme/api/actions/{}", name);
+        let entry = StateEntry {
+            fingerprint: state.fingerprint(&key, value),
+            updated_at: UPDATED_AT.to_string(),
+        };
+        state.secrets.insert(key, entry);
+    }
+    state
+}
+
+#[tokio::test]
+async fn build_plan_classifies_declared_and_remote_secrets() {
+    let dir = tempdir().expect("tempdir");
+    let state = recorded_state(&dir.path().join("state.json"));
+    let (_stub, client) = github_with_secrets();
+
+    let plan = plan::build_plan(
+        &client,
+        &config(),
+        std::path::Path::new("config.yaml"),
+        &state,
+        true,
+        &[],
+    )
+    .await
+    .expect("plan");
+
+    let actions: Vec<_> = plan
+        .changes
+        .iter()
+        .map(|change| {
+            (
+                change.name.as_str(),
+                change.action,
+                change.reason.as_deref(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        actions,
+        [
+            ("CHANGED", Action::Update, Some("value changed")),
+            ("NEW_KEY", Action::Create, None),
+            ("OLD", Action::Delete, Some("not in config")),
+            ("SAME", Action::Unchanged, None),
+        ]
+    );
+    assert_eq!(plan.salt, state.salt);
+}
+
+#[tokio::test]
+async fn apply_refuses_a_plan_made_against_another_state() {
+    let dir = tempdir().expect("tempdir");
+    let state_path = dir.path().join("state.json");
+    let state = recorded_state(&state_path);
+    let (stub, client) = github_with_secrets();
+    let plan = plan::build_plan(
+        &client,
+        &config(),
+        std::path::Path::new("config.yaml"),
+        &state,
+        true,
+        &[],
+    )
+    .await
+    .expect("plan");
+
+    // The state file is replaced, e.g. deleted and re-created, after planning
+    let mut replaced = State::load_or_init(&state_path).expect("init");
+    let err = plan::apply_plan(&client, &config(), &plan, &mut replaced, &state_path, &[])
+        .await
+        .unwrap_err();
+
+    assert!(err.to_string().contains("state file was replaced"));
+    assert!(stub.calls("PUT").is_empty() && stub.calls("DELETE").is_empty());
+    assert!(!state_path.exists());
+}
+
+#[tokio::test]
+async fn apply_refuses_deleting_secrets_the_config_now_declares_or_protects() {
+    let dir = tempdir().expect("tempdir");
+    let state_path = dir.path().join("state.json");
+    recorded_state(&state_path).save(&state_path).expect("save");
+    let (stub, client) = github_with_secrets();
+    let plan = plan::build_plan(
+        &client,
+        &config(),
+        std::path::Path::new("config.yaml"),
+        &State::load_or_init(&state_path).expect("load"),
+        true,
+        &[],
+    )
+    .await
+    .expect("plan");
+    assert!(
+        plan.changes
+            .iter()
+            .any(|c| c.name == "OLD" && c.action == Action::Delete)
+    );
+
+    // `OLD` is declared again after planning
+    let mut declared = config();
+    declared.env.insert("old".to_string(), "back".to_string());
+    let err = plan::apply_plan(
+        &client,
+        &declared,
+        &plan,
+        &mut State::load_or_init(&state_path).expect("load"),
+        &state_path,
+        &[],
+    )
+    .await
+    .unwrap_err();
+    assert!(err.to_string().contains("planned for deletion"), "{}", err);
+
+    // `OLD` is protected after planning
+    let protected = gsm::config::compile_patterns(&["OL*".to_string()]).expect("patterns");
+    let err = plan::apply_plan(
+        &client,
+        &config(),
+        &plan,
+        &mut State::load_or_init(&state_path).expect("load"),
+        &state_path,
+        &protected,
+    )
+    .await
+    .unwrap_err();
+    assert!(err.to_string().contains("planned for deletion"), "{}", err);
+
+    assert!(stub.calls("PUT").is_empty() && stub.calls("DELETE").is_empty());
+}
@@ -0,0 +1,327 @@
+mod common;
+
+use base64::{Engine as _, engine::general_purpose};
+use common::Stub;
+use gsm::cli::crypto_ops::{self, Credentials, Protection};
+use gsm::cli::key_provider::{KeyArgs, KeySource};
+use gsm::cli::push::{self, ItemReport, PushReport, ScopeReport};
+use gsm::crypto::Kdf;
+use gsm::github::GithubClient;
+use sodiumoxide::crypto::{box_, sealedbox};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[test]
+fn report_json_includes_errors_only_for_failed_items() {
+    let report = PushReport {
+        scopes: vec![ScopeReport {
+            repository: Some("repo1".to_string()),
+            items: vec![
+                ItemReport {
+                    name: "API_KEY".to_string(),
+                    status: "pushed".to_string(),
+                    error: None,
+                },
+                ItemReport {
+                    name: "REGION (variable)".to_string(),
+                    status: "failed".to_string(),
+                    error: Some("HTTP error: 404 Not Found".to_string()),
+                },
+            ],
+        }],
+        ..Default::default()
+    };
+    assert_eq!(report.scopes[0].failed(), 1);
+
+    let json = serde_json::to_value(&report).expect("serialize");
+    let items = &json["scopes"][0]["items"];
+    assert!(items[0].get("error").is_none());
+    assert_eq!(items[1]["error"], "HTTP error: 404 Not Found");
+    assert_eq!(json["scopes"][0]["repository"], "repo1");
+}
+
+/// A key file readable only by its owner, as `file:` key sources require
+fn key_file(dir: &Path, key: &str) -> KeyArgs {
+    let path = dir.join("key");
+    fs::write(&path, key).expect("write key");
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o600)).expect("chmod");
+    }
+    KeyArgs {
+        key_source: Some(KeySource::File(path)),
+        ..KeyArgs::default()
+    }
+}
+
+/// A config for the `api` repository, encrypted with `password`
+fn encrypted_config(dir: &Path, password: &str) -> PathBuf {
+    let path = dir.join("prod.yaml");
+    let mut document: serde_yaml::Value =
+        serde_yaml::from_str("org: acme\nrepositories: [api]\nenv:\n  API_KEY: hunter2\n")
+            .expect("yaml");
+    let mut credentials = Credentials {
+        password: Some(password.as_bytes().to_vec()),
+        ..Default::default()
+    };
+    let protection = Protection::Password(Kdf::Argon2id {
+        memory_kib: 1024,
+        time: 1,
+        parallelism: 1,
+    });
+    crypto_ops::encrypt_document(&mut document, &mut credentials, &protection).expect("encrypt");
+    fs::write(&path, serde_yaml::to_string(&document).expect("yaml")).expect("write");
+    path
+}
+
+#[tokio::test]
+async fn pushes_secrets_decrypted_from_an_encrypted_file() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let path = encrypted_config(dir.path(), "correct horse");
+
+    let configs = push::load_configs(&path, &key_file(dir.path(), "correct horse")).expect("load");
+    assert_eq!(configs.len(), 1);
+    let (_, config) = &configs[0];
+
+    let (public_key, secret_key) = box_::gen_keypair();
+    let encoded_key = general_purpose::STANDARD.encode(public_key.as_ref());
+    let stub = Stub::start(move |request| {
+        if request.path.ends_with("/public-key") {
+            let body = serde_json::json!({ "key": encoded_key, "key_id": "k1" });
+            (200, body.to_string())
+        } else {
+            (201, "{}".to_string())
+        }
+    });
+    let client = GithubClient::new("token".to_string(), Some(stub.url.clone()));
+    let report = push::push_config(&client, config, false)
+        .await
+        .expect("push");
+    assert_eq!(report.summary.secrets, 1);
+
+    let put = stub
+        .requests()
+        .into_iter()
+        .find(|request| request.method == "PUT")
+        .expect("secret pushed");
+    assert_eq!(put.path, "/repos/acme/api/actions/secrets/API_KEY");
+    let body: serde_json::Value = serde_json::from_str(&put.body).expect("json");
+    let sealed = general_purpose::STANDARD
+        .decode(body["encrypted_value"].as_str().expect("value"))
+        .expect("base64");
+    let plaintext = sealedbox::open(&sealed, &public_key, &secret_key).expect("open");
+    assert_eq!(plaintext, b"hunter2");
+}
+
+#[test]
+fn encrypted_file_with_wrong_key_is_rejected_before_pushing() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let path = encrypted_config(dir.path(), "correct horse");
+
+    assert!(push::load_configs(&path, &key_file(dir.path(), "wrong")).is_err());
+}
+
+#[test]
+fn directory_push_skips_files_other_files_reference() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let configs = dir.path().join("configs");
+    fs::create_dir(&configs).expect("mkdir");
+    fs::write(
+        configs.join("base.yaml"),
+        "org: acme\nrepositories: [api]\nenv:\n  REGION: eu\n",
+    )
+    .expect("write");
+    fs::write(configs.join("shared.yaml"), "env:\n  SHARED: yes\n").expect("write");
+    fs::write(
+        configs.join("prod.yaml"),
+        "extends: base.yaml\ninclude: [shared.yaml]\nenv:\n  DB_URL: prod-db\n",
+    )
+    .expect("write");
+    fs::write(
+        configs.join("staging.yml"),
+        "org: acme\nrepositories: [web]\nenv:\n  DB_URL: staging-db\n",
+    )
+    .expect("write");
+    fs::write(configs.join("notes.txt"), "not a config").expect("write");
+
+    let loaded = push::load_configs(&configs, &KeyArgs::default()).expect("load");
+    let names: Vec<_> = loaded
+        .iter()
+        .map(|(file, _)| {
+            file.file_name()
+                .expect("name")
+                .to_string_lossy()
+                .into_owned()
+        })
+        .collect();
+    assert_eq!(names, ["prod.yaml", "staging.yml"]);
+
+    let prod = &loaded[0].1;
+    assert_eq!(prod.repositories, ["api"]);
+    assert!(prod.env.contains_key("REGION") && prod.env.contains_key("SHARED"));
+}
+
+#[test]
+fn continue_on_error_pushes_past_failed_files_and_secrets() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let configs = dir.path().join("configs");
+    fs::create_dir(&configs).expect("mkdir");
+    // Its repositories cannot be looked up, so nothing of it is pushed
+    fs::write(
+        configs.join("a.yaml"),
+        "org: broken\nselect_repositories:\n  names: [\"svc-*\"]\nenv:\n  TOKEN: t\n",
+    )
+    .expect("write");
+    fs::write(
+        configs.join("b.yaml"),
+        "org: acme\nrepositories: [api, web]\nenv:\n  A_KEY: a\n  B_KEY: b\n",
+    )
+    .expect("write");
+
+    let (public_key, _) = box_::gen_keypair();
+    let encoded_key = general_purpose::STANDARD.encode(public_key.as_ref());
+    let stub = Stub::start(move |request| {
+        if request.path.starts_with("/orgs/broken/repos")
+            || request.path == "/repos/acme/api/actions/secrets/A_KEY"
+        {
+            (500, "{}".to_string())
+        } else if request.path.ends_with("/public-key") {
+            let body = serde_json::json!({ "key": encoded_key, "key_id": "k1" });
+            (200, body.to_string())
+        } else {
+            (201, "{}".to_string())
+        }
+    });
+    let report_path = dir.path().join("report.json");
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_gsm"))
+        .args(["push", "--continue-on-error", "--max-retries", "0"])
+        .arg("--file")
+        .arg(&configs)
+        .arg("--api-url")
+        .arg(&stub.url)
+        .arg("--report-json")
+        .arg(&report_path)
+        .env("GITHUB_TOKEN", "token")
+        .output()
+        .expect("run gsm");
+
+    assert_eq!(
+        output.status.code(),
+        Some(i32::from(push::EXIT_PARTIAL_FAILURE)),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let mut puts = stub.calls("PUT");
+    puts.sort();
+    assert_eq!(
+        puts,
+        [
+            "PUT /repos/acme/api/actions/secrets/A_KEY",
+            "PUT /repos/acme/api/actions/secrets/B_KEY",
+            "PUT /repos/acme/web/actions/secrets/A_KEY",
+            "PUT /repos/acme/web/actions/secrets/B_KEY",
+        ]
+    );
+
+    let report: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&report_path).expect("read")).expect("json");
+    assert_eq!(report["summary"]["failed"], 2);
+    assert_eq!(report["summary"]["secrets"], 3);
+    let failed: Vec<&str> = report["scopes"]
+        .as_array()
+        .expect("scopes")
+        .iter()
+        .flat_map(|scope| scope["items"].as_array().expect("items"))
+        .filter(|item| item["status"] == "failed")
+        .map(|item| item["name"].as_str().expect("name"))
+        .collect();
+    assert_eq!(failed.len(), 2);
+    assert!(failed[0].ends_with("a.yaml"), "{:?}", failed);
+    assert_eq!(failed[1], "A_KEY");
+}
+
+#[tokio::test]
+async fn requests_in_flight_stay_within_the_concurrency_limit() {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    let config: gsm::config::Config = serde_yaml::from_str(
+        "org: acme\nrepositories: [slow, r2, r3, r4, r5]\nenv:\n  A_KEY: a\n  B_KEY: b\n",
+    )
+    .expect("yaml");
+    let (public_key, _) = box_::gen_keypair();
+    let encoded_key = general_purpose::STANDARD.encode(public_key.as_ref());
+    let in_flight = Arc::new(AtomicUsize::new(0));
+    let most = Arc::new(AtomicUsize::new(0));
+    let (current, peak) = (Arc::clone(&in_flight), Arc::clone(&most));
+    let stub = Stub::start(move |request| {
+        peak.fetch_max(current.fetch_add(1, Ordering::SeqCst) + 1, Ordering::SeqCst);
+        // The first repository finishes last
+        let delay = if request.path.starts_with("/repos/acme/slow/") {
+            100
+        } else {
+            20
+        };
+        std::thread::sleep(Duration::from_millis(delay));
+        current.fetch_sub(1, Ordering::SeqCst);
+        if request.path.ends_with("/public-key") {
+            let body = serde_json::json!({ "key": encoded_key, "key_id": "k1" });
+            (200, body.to_string())
+        } else {
+            (201, "{}".to_string())
+        }
+    });
+    let client = GithubClient::new("token".to_string(), Some(stub.url.clone())).with_concurrency(2);
+
+    let report = push::push_config(&client, &config, false)
+        .await
+        .expect("push");
+
+    assert_eq!(report.summary.secrets, 10);
+    assert_eq!(most.load(Ordering::SeqCst), 2);
+    let order: Vec<_> = report
+        .scopes
+        .iter()
+        .map(|scope| scope.repository.as_deref().expect("repository"))
+        .collect();
+    assert_eq!(order, ["slow", "r2", "r3", "r4", "r5"]);
+}
+
+#[tokio::test]
+async fn environments_are_created_before_their_secrets_are_pushed() {
+    let config: gsm::config::Config = serde_yaml::from_str(
+        "org: acme\nrepositories: [api]\nenv: {}\nenvironments:\n  prod/eu west:\n    env:\n      TOKEN: t\n",
+    )
+    .expect("yaml");
+    let (public_key, _) = box_::gen_keypair();
+    let encoded_key = general_purpose::STANDARD.encode(public_key.as_ref());
+    let stub = Stub::start(move |request| {
+        if request.method == "GET"
+            && request.path == "/repos/acme/api/environments/prod%2Feu%20west"
+        {
+            (404, "{}".to_string())
+        } else if request.path.ends_with("/public-key") {
+            let body = serde_json::json!({ "key": encoded_key, "key_id": "k1" });
+            (200, body.to_string())
+        } else {
+            (201, "{}".to_string())
+        }
+    });
+    let client = GithubClient::new("token".to_string(), Some(stub.url.clone()));
+
+    let report = push::push_config(&client, &config, false)
+        .await
+        .expect("push");
+
+    assert_eq!(report.summary.secrets, 1);
+    assert_eq!(
+        stub.calls("PUT"),
+        [
+            "PUT /repos/acme/api/environments/prod%2Feu%20west",
+            "PUT /repos/acme/api/environments/prod%2Feu%20west/secrets/TOKEN",
+        ]
+    );
+}
@@ -0,0 +1,99 @@
+mod common;
+
+use std::collections::BTreeSet;
+
+use common::Stub;
+use gsm::cli::repositories;
+use gsm::config::Config;
+use gsm::github::GithubClient;
+
+fn config(yaml: &str) -> Config {
+    serde_yaml::from_str(yaml).expect("parse")
+}
+
+#[test]
+fn merge_keeps_explicit_order_and_applies_exclusions() {
+    let mut config = config(
+        r#"
+org: acme
+repositories: [zeta, legacy-app, alpha]
+select_repositories:
+  names: ["svc-*"]
+  exclude: ["legacy-*", "svc-old"]
+env: {}
+"#,
+    );
+    let selected = BTreeSet::from([
+        "svc-b".to_string(),
+        "svc-a".to_string(),
+        "svc-old".to_string(),
+        "ALPHA".to_string(),
+    ]);
+    repositories::merge(&mut config, selected).expect("merge");
+    assert_eq!(config.repositories, ["zeta", "alpha", "svc-a", "svc-b"]);
+}
+
+#[tokio::test]
+async fn resolve_selects_by_name_pattern_topic_and_team() {
+    let stub = Stub::start(|request| {
+        let repositories = match request.path.split('?').next().unwrap_or_default() {
+            "/orgs/acme/repos" => serde_json::json!([
+                { "name": "svc-billing", "topics": [] },
+                { "name": "svc-retired", "topics": [], "archived": true },
+                { "name": "website", "topics": ["Deploy"] },
+                { "name": "docs", "topics": ["internal"] },
+            ]),
+            "/orgs/acme/teams/platform%20team/repos" => serde_json::json!([
+                { "name": "infra" },
+                { "name": "old-infra", "archived": true },
+            ]),
+            _ => return (404, "{}".to_string()),
+        };
+        (200, repositories.to_string())
+    });
+    let client = GithubClient::new("token".to_string(), Some(stub.url.clone()));
+    let mut config = config(
+        r#"
+org: acme
+repositories: [zeta]
+select_repositories:
+  names: ["svc-*"]
+  topics: [deploy]
+  teams: ["platform team"]
+env: {}
+"#,
+    );
+
+    repositories::resolve(&client, &mut config)
+        .await
+        .expect("resolve");
+
+    // Archived repositories are skipped and topics match case-insensitively
+    assert_eq!(
+        config.repositories,
+        ["zeta", "infra", "svc-billing", "website"]
+    );
+    let mut calls = stub.calls("GET");
+    calls.sort();
+    assert_eq!(
+        calls,
+        [
+            "GET /orgs/acme/repos?per_page=100&page=1",
+            "GET /orgs/acme/teams/platform%20team/repos?per_page=100&page=1",
+        ]
+    );
+}
+
+#[tokio::test]
+async fn resolve_without_selection_rules_asks_github_nothing() {
+    let stub = Stub::start(|_| (500, "{}".to_string()));
+    let client = GithubClient::new("token".to_string(), Some(stub.url.clone()));
+    let mut config = config("org: acme\nrepositories: [api]\nenv: {}\n");
+
+    repositories::resolve(&client, &mut config)
+        .await
+        .expect("resolve");
+
+    assert_eq!(config.repositories, ["api"]);
+    assert!(stub.requests().is_empty());
+}
@@ -0,0 +1,69 @@
+mod common;
+
+use common::Stub;
+use gsm::cli::sync;
+use gsm::config::{self, Config};
+use gsm::github::GithubClient;
+
+/// Remote secrets: one declared, one protected and one leftover per store
+fn remote_secrets() -> Stub {
+    Stub::start(|request| {
+        let names: &[&str] = if request.path.starts_with("/repos/acme/api/actions/secrets?") {
+            &["API_KEY", "DEPLOY_KEY", "OLD_TOKEN"]
+        } else if request
+            .path
+            .starts_with("/repos/acme/api/environments/staging/secrets?")
+        {
+            &["DB_URL", "STALE"]
+        } else {
+            return (204, String::new());
+        };
+        let secrets: Vec<_> = names
+            .iter()
+            .map(|name| serde_json::json!({ "name": name, "updated_at": "2024-01-01T00:00:00Z" }))
+            .collect();
+        let body = serde_json::json!({ "total_count": secrets.len(), "secrets": secrets });
+        (200, body.to_string())
+    })
+}
+
+fn config() -> Config {
+    serde_yaml::from_str(
+        "org: acme\nrepositories: [api]\nenv:\n  api_key: x\nenvironments:\n  staging:\n    env:\n      DB_URL: y\n",
+    )
+    .expect("config")
+}
+
+#[tokio::test]
+async fn undeclared_secrets_are_reported_without_prune() {
+    let stub = remote_secrets();
+    let client = GithubClient::new("token".to_string(), Some(stub.url.clone()));
+    let protected = config::compile_patterns(&["DEPLOY_*".to_string()]).expect("patterns");
+
+    let undeclared = sync::undeclared_secrets(&client, &config(), &protected, false)
+        .await
+        .expect("sync");
+
+    assert_eq!(undeclared, 2);
+    assert!(stub.calls("DELETE").is_empty());
+}
+
+#[tokio::test]
+async fn prune_deletes_undeclared_secrets_but_not_protected_ones() {
+    let stub = remote_secrets();
+    let client = GithubClient::new("token".to_string(), Some(stub.url.clone()));
+    let protected = config::compile_patterns(&["DEPLOY_*".to_string()]).expect("patterns");
+
+    let undeclared = sync::undeclared_secrets(&client, &config(), &protected, true)
+        .await
+        .expect("sync");
+
+    assert_eq!(undeclared, 0);
+    assert_eq!(
+        stub.calls("DELETE"),
+        [
+            "DELETE /repos/acme/api/actions/secrets/OLD_TOKEN",
+            "DELETE /repos/acme/api/environments/staging/secrets/STALE",
+        ]
+    );
+}
@@ -0,0 +1,176 @@
+use base64::{Engine as _, engine::general_purpose};
+
+mod common;
+
+use common::Stub;
+use gsm::cli::crypto_ops::{self, Credentials, Protection};
+use gsm::crypto::{Kdf, TransitKey, Vault};
+
+const TOKEN: &str = "test-token";
+
+/// A stand-in for Vault's Transit `encrypt` and `decrypt` endpoints, whose
+/// "master key" is an XOR with a byte the client never sees
+fn transit_server() -> Stub {
+    Stub::start(|request| {
+        let xor = |data: Vec<u8>| data.into_iter().map(|b| b ^ 0x5a).collect::<Vec<_>>();
+        if request.header("x-vault-token") != Some(TOKEN) {
+            return (403, r#"{"errors":["permission denied"]}"#.to_string());
+        }
+        let body: serde_json::Value = serde_json::from_str(&request.body).expect("json");
+        match request.path.as_str() {
+            "/v1/transit/encrypt/gsm" => {
+                let plaintext = general_purpose::STANDARD
+                    .decode(body["plaintext"].as_str().expect("plaintext"))
+                    .expect("base64");
+                let ciphertext = general_purpose::STANDARD.encode(xor(plaintext));
+                let data = serde_json::json!({ "data": { "ciphertext": format!("vault:v1:{}", ciphertext) } });
+                (200, data.to_string())
+            }
+            "/v1/transit/decrypt/gsm" => {
+                let ciphertext = body["ciphertext"].as_str().expect("ciphertext");
+                let ciphertext = general_purpose::STANDARD
+                    .decode(ciphertext.trim_start_matches("vault:v1:"))
+                    .expect("base64");
+                let plaintext = general_purpose::STANDARD.encode(xor(ciphertext));
+                (
+                    200,
+                    serde_json::json!({ "data": { "plaintext": plaintext } }).to_string(),
+                )
+            }
+            _ => (404, r#"{"errors":[]}"#.to_string()),
+        }
+    })
+}
+
+/// Credentials for the local stand-in, which only speaks plain HTTP
+fn vault_credentials(address: &str, token: &str) -> Credentials {
+    Credentials {
+        vault: Some(Vault::new(address, token, true).expect("vault")),
+        ..Credentials::default()
+    }
+}
+
+fn encrypted_document(address: &str) -> serde_yaml::Value {
+    let protection = Protection::Transit(TransitKey {
+        mount: "transit".to_string(),
+        key: "gsm".to_string(),
+    });
+    let mut document: serde_yaml::Value =
+        serde_yaml::from_str("org: example\nenv:\n  TOKEN: wrapped\n").expect("parse");
+    let mut credentials = vault_credentials(address, TOKEN);
+    crypto_ops::encrypt_document(&mut document, &mut credentials, &protection).expect("encrypt");
+    assert!(!crypto_ops::needs_migration(&mut document, &protection).expect("check"));
+    document
+}
+
+#[test]
+fn transit_wraps_the_file_key() {
+    let server = transit_server();
+    let address = server.url.clone();
+    let document = encrypted_document(&address);
+    let metadata = &document[crypto_ops::METADATA_KEY];
+    assert!(metadata.get("kdf").is_none());
+    assert_eq!(metadata["transit"]["key"], "gsm");
+    let wrapped = metadata["transit"]["wrapped_key"]
+        .as_str()
+        .expect("wrapped");
+    assert!(wrapped.starts_with("vault:v1:"), "{}", wrapped);
+
+    let mut decrypted = document.clone();
+    crypto_ops::decrypt_document(&mut decrypted, &mut vault_credentials(&address, TOKEN))
+        .expect("decrypt");
+    assert_eq!(decrypted["env"]["TOKEN"], "wrapped");
+
+    let mut wrong_token = vault_credentials(&address, "wrong");
+    let error = crypto_ops::decrypt_document(&mut document.clone(), &mut wrong_token).unwrap_err();
+    assert!(error.to_string().contains("403"), "{}", error);
+}
+
+#[tokio::test]
+async fn transit_works_inside_the_async_runtime() {
+    let server = transit_server();
+    let address = server.url.clone();
+    let mut document = encrypted_document(&address);
+    crypto_ops::decrypt_document(&mut document, &mut vault_credentials(&address, TOKEN))
+        .expect("decrypt");
+    assert_eq!(document["env"]["TOKEN"], "wrapped");
+}
+
+#[test]
+fn vault_addresses_must_use_https_unless_allowed() {
+    assert!(Vault::new("https://vault.example.com:8200", TOKEN, false).is_ok());
+    let error = Vault::new("http://vault.example.com", TOKEN, false).unwrap_err();
+    assert!(
+        error.to_string().contains("--vault-allow-http"),
+        "{}",
+        error
+    );
+    assert!(Vault::new("http://vault.example.com", TOKEN, true).is_ok());
+    assert!(Vault::new("vault.example.com", TOKEN, true).is_err());
+}
+
+#[test]
+fn addresses_in_files_never_receive_the_token() {
+    let server = transit_server();
+    let address = server.url.clone();
+    let rogue = Stub::start(|_| (200, "{}".to_string()));
+    let mut document = encrypted_document(&address);
+    document[crypto_ops::METADATA_KEY]["transit"]["address"] = rogue.url.clone().into();
+
+    let error =
+        crypto_ops::decrypt_document(&mut document, &mut vault_credentials(&address, TOKEN))
+            .unwrap_err();
+    assert!(error.to_string().contains("Integrity"), "{}", error);
+    assert!(rogue.requests().is_empty());
+
+    let dir = tempfile::tempdir().expect("tempdir");
+    std::fs::write(
+        dir.path().join(".gsm"),
+        format!("transit:\n  address: {}\n  key: gsm\n", rogue.url),
+    )
+    .expect("write");
+    let plain = serde_yaml::Value::Mapping(Default::default());
+    let error = crypto_ops::protection_for(&dir.path().join("app.yaml"), &plain, &Kdf::default())
+        .unwrap_err();
+    assert!(error.to_string().contains("VAULT_ADDR"), "{}", error);
+}
+
+#[test]
+fn mounts_and_keys_must_be_plain_names() {
+    let server = transit_server();
+    let address = server.url.clone();
+    let vault = Stub::start(|_| (200, "{}".to_string()));
+    let file_key = [7u8; 32];
+    for (mount, key) in [
+        ("sys/../../auth/token", "gsm"),
+        ("transit", "../../sys/policy/root"),
+        ("transit", "gsm/extra"),
+        ("transit//keys", "gsm"),
+        ("transit", ""),
+    ] {
+        let transit = TransitKey {
+            mount: mount.to_string(),
+            key: key.to_string(),
+        };
+        let vault = Vault::new(&vault.url, TOKEN, true).expect("vault");
+        let error = transit.wrap_key(&file_key, &vault).unwrap_err();
+        assert!(error.to_string().contains("invalid"), "{}", error);
+    }
+    assert!(
+        TransitKey {
+            mount: "/secret/transit/".to_string(),
+            key: "gsm_key-1".to_string(),
+        }
+        .check()
+        .is_ok()
+    );
+
+    // A hostile mount in a file's metadata is refused before the key is unwrapped
+    let mut document = encrypted_document(&address);
+    document[crypto_ops::METADATA_KEY]["transit"]["mount"] = "sys/../../auth/token".into();
+    let error =
+        crypto_ops::decrypt_document(&mut document, &mut vault_credentials(&vault.url, TOKEN))
+            .unwrap_err();
+    assert!(error.to_string().contains("invalid mount"), "{}", error);
+    assert!(vault.requests().is_empty());
+}